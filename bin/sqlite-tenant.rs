@@ -0,0 +1,858 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::{json, Value};
+use sqlite_tenant::prelude::types::ValueRef;
+use sqlite_tenant::prelude::*;
+
+type CliResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Manage the tenants registered in a sqlite-tenant master database.
+#[derive(Parser)]
+#[command(name = "sqlite-tenant", version, about)]
+struct Cli
+{
+    /// Path to the master database.
+    #[arg(short, long)]
+    master: PathBuf,
+    /// Print JSON instead of human-readable output.
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command
+{
+    /// List every registered tenant.
+    List,
+    /// Register a new tenant, creating the master database if it does not exist yet.
+    Add
+    {
+        tenant_id: String,
+        /// Path to the tenant database. In-memory tenants can not be added, they would be gone once the CLI exits.
+        #[arg(long)]
+        path: PathBuf,
+    },
+    /// Remove a tenant from the master database.
+    Remove
+    {
+        tenant_id: String,
+        /// Also delete the tenant database file, along with the journal files next to it.
+        #[arg(long)]
+        delete_file: bool,
+    },
+    /// Show a tenant along with its access stats and quota usage.
+    Info
+    {
+        tenant_id: String
+    },
+    /// Print the amount of registered tenants.
+    Count,
+    /// Copy a tenant database into a new file.
+    Backup
+    {
+        tenant_id: String, destination: PathBuf
+    },
+    /// Replace a tenant database with the contents of a backup.
+    Restore
+    {
+        tenant_id: String, source: PathBuf
+    },
+    /// Dump the schema and rows of a tenant as SQL.
+    Export
+    {
+        tenant_id: String,
+        /// Write the dump to a file instead of stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Run a SQL file, such as an export, against a tenant.
+    Import
+    {
+        tenant_id: String, file: PathBuf
+    },
+    /// Run a SQL migration file against every tenant, each in its own transaction.
+    Migrate
+    {
+        file: PathBuf,
+        /// Only migrate these tenants. Can be repeated.
+        #[arg(long = "tenant")]
+        tenants: Vec<String>,
+        /// The amount of tenants migrated at once.
+        #[arg(long, default_value_t = 1)]
+        parallelism: usize,
+    },
+    /// Rebuild tenant databases to reclaim free space, every tenant if none are given.
+    Vacuum
+    {
+        tenants: Vec<String>
+    },
+    /// Check that the master database is writable and tenant files are intact.
+    Check
+    {
+        #[arg(long, value_enum, default_value_t = Integrity::Quick)]
+        integrity: Integrity,
+        /// Only run the integrity check on this many tenants.
+        #[arg(long)]
+        sample: Option<usize>,
+        /// Report files in this directory that are not registered tenants.
+        #[arg(long)]
+        storage_dir: Option<PathBuf>,
+    },
+    /// Open an interactive SQL prompt against a tenant.
+    Shell
+    {
+        tenant_id: String
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Integrity
+{
+    None,
+    Quick,
+    Full,
+}
+
+impl From<Integrity> for IntegrityCheck
+{
+    fn from(integrity: Integrity) -> Self
+    {
+        match integrity {
+            Integrity::None => IntegrityCheck::None,
+            Integrity::Quick => IntegrityCheck::Quick,
+            Integrity::Full => IntegrityCheck::Full,
+        }
+    }
+}
+
+fn main() -> ExitCode
+{
+    let cli = Cli::parse();
+
+    match run(cli) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+impl Command
+{
+    /// Whether the command may write to the master database or a tenant database.
+    fn writes(&self) -> bool
+    {
+        !matches!(
+            self,
+            Command::List
+                | Command::Info { .. }
+                | Command::Count
+                | Command::Backup { .. }
+                | Command::Export { .. }
+                | Command::Check { .. }
+        )
+    }
+}
+
+fn run(cli: Cli) -> CliResult<ExitCode>
+{
+    if !cli.master.exists() && !matches!(cli.command, Command::Add { .. }) {
+        return Err(format!("master database {} does not exist", cli.master.display()).into());
+    }
+
+    // Commands that only read skip the check that the master directory is writable, so they keep working on a
+    // read-only copy.
+    let config = if cli.command.writes() {
+        Configuration::builder().master_db_path(&cli.master).build()?
+    } else {
+        Configuration {
+            master_db_path: Some(cli.master.clone()),
+            ..Configuration::default()
+        }
+    };
+
+    let mut manager = MultiTenantManager::new(config)?;
+
+    let json = cli.json;
+
+    match cli.command {
+        Command::List => {
+            let tenants = manager.list_tenants()?;
+
+            emit(json, Value::Array(tenants.iter().map(tenant_json).collect()), || {
+                tenants
+                    .iter()
+                    .map(|tenant| format!("{}\t{}\t{}", tenant.tenant_id, display_path(&tenant.path), tenant.created_at))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            });
+        }
+        Command::Add { tenant_id, path } => {
+            manager.add_tenant(&tenant_id, Some(path.clone()))?;
+
+            emit(json, json!({ "added": tenant_id, "path": path }), || {
+                format!("Added {} ({})", tenant_id, path.display())
+            });
+        }
+        Command::Remove { tenant_id, delete_file } => {
+            let tenant = find_tenant(&manager, &tenant_id)?;
+            manager.remove_tenant(&tenant_id)?;
+
+            let deleted = match tenant.path.filter(|_| delete_file) {
+                Some(path) if path.exists() => {
+                    for file in database_files(&path).into_iter().filter(|file| file.exists()) {
+                        fs::remove_file(&file)?;
+                    }
+
+                    Some(path)
+                }
+                _ => None,
+            };
+
+            emit(
+                json,
+                json!({ "removed": tenant_id, "deleted_file": deleted }),
+                || match &deleted {
+                    Some(path) => format!("Removed {} and deleted {}", tenant_id, path.display()),
+                    None => format!("Removed {}", tenant_id),
+                },
+            );
+        }
+        Command::Info { tenant_id } => {
+            let tenant = find_tenant(&manager, &tenant_id)?;
+            let stats = manager.tenant_stats(&tenant_id)?;
+            let usage = manager.quota_usage(&tenant_id)?;
+            let size = tenant
+                .path
+                .as_ref()
+                .and_then(|path| fs::metadata(path).ok())
+                .map(|metadata| metadata.len());
+
+            let info = json!({
+                "tenant": tenant_json(&tenant),
+                "size_bytes": size,
+                "last_accessed_at": stats.as_ref().and_then(|stats| stats.last_accessed_at.clone()),
+                "access_count": stats.as_ref().map(|stats| stats.access_count),
+                "open_count": stats.as_ref().map(|stats| stats.open_count),
+                "page_count": usage.page_count,
+                "max_page_count": usage.max_page_count,
+                "max_connections": usage.max_connections,
+                "row_limits": usage.tables.iter().map(|table| json!({
+                    "table": table.table,
+                    "rows": table.rows,
+                    "max_rows": table.max_rows,
+                })).collect::<Vec<_>>(),
+            });
+
+            emit(json, info.clone(), || {
+                let mut lines = vec![
+                    format!("tenant:        {}", tenant.tenant_id),
+                    format!("path:          {}", display_path(&tenant.path)),
+                    format!("created at:    {}", tenant.created_at),
+                    format!("size:          {}", optional(size.map(|size| format!("{} bytes", size)))),
+                    format!("last accessed: {}", optional(info["last_accessed_at"].as_str())),
+                    format!("accesses:      {}", optional(info["access_count"].as_u64())),
+                    format!("opens:         {}", optional(info["open_count"].as_u64())),
+                    format!("pages:         {} / {}", usage.page_count, optional(usage.max_page_count)),
+                    format!("connections:   {}", optional(usage.max_connections)),
+                ];
+
+                lines.extend(
+                    usage
+                        .tables
+                        .iter()
+                        .map(|table| format!("rows in {}: {} / {}", table.table, table.rows, table.max_rows)),
+                );
+
+                lines.join("\n")
+            });
+        }
+        Command::Count => {
+            let count = manager.tenant_count();
+            emit(json, json!({ "count": count }), || count.to_string());
+        }
+        Command::Backup { tenant_id, destination } => {
+            let path = tenant_path(&manager, &tenant_id)?;
+
+            if destination.exists() {
+                return Err(format!("{} already exists", destination.display()).into());
+            }
+
+            let connection = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+            connection.execute("VACUUM INTO ?1;", params![destination.to_str()])?;
+
+            emit(json, json!({ "tenant_id": tenant_id, "backup": destination }), || {
+                format!("Backed up {} to {}", tenant_id, destination.display())
+            });
+        }
+        Command::Restore { tenant_id, source } => {
+            let path = tenant_path(&manager, &tenant_id)?;
+
+            // The restore writes to the file directly, past the read-only connections the manager hands out.
+            if manager.access_mode(&tenant_id)? == AccessMode::ReadOnly {
+                return Err(format!("{} is read-only", tenant_id).into());
+            }
+
+            if !source.is_file() {
+                return Err(format!("{} does not exist", source.display()).into());
+            }
+
+            let mut connection = Connection::open(&path)?;
+            connection.restore(DatabaseName::Main, &source, None::<fn(backup::Progress)>)?;
+
+            emit(json, json!({ "tenant_id": tenant_id, "restored_from": source }), || {
+                format!("Restored {} from {}", tenant_id, source.display())
+            });
+        }
+        Command::Export { tenant_id, output } => {
+            let connection = tenant_connection(&mut manager, &tenant_id)?.connection;
+            let dump = export_sql(&connection)?;
+
+            match output {
+                Some(output) => {
+                    fs::write(&output, &dump)?;
+                    emit(json, json!({ "tenant_id": tenant_id, "output": output }), || {
+                        format!("Exported {} to {}", tenant_id, output.display())
+                    });
+                }
+                None => print!("{}", dump),
+            }
+        }
+        Command::Import { tenant_id, file } => {
+            let sql = fs::read_to_string(&file)?;
+            let connection = tenant_connection(&mut manager, &tenant_id)?.connection;
+            connection.execute_batch(&sql)?;
+
+            emit(json, json!({ "tenant_id": tenant_id, "imported": file }), || {
+                format!("Imported {} into {}", file.display(), tenant_id)
+            });
+        }
+        Command::Migrate {
+            file,
+            tenants,
+            parallelism,
+        } => {
+            let sql = fs::read_to_string(&file)?;
+
+            let report = manager.for_each_tenant(tenant_filter(tenants), parallelism, |_, connection| {
+                let tx = connection.unchecked_transaction()?;
+                tx.execute_batch(&sql)?;
+                tx.commit()?;
+                Ok(())
+            })?;
+
+            return Ok(emit_fan_out(json, "migrated", report));
+        }
+        Command::Vacuum { tenants } => {
+            let report = manager.for_each_tenant(tenant_filter(tenants), 1, |_, connection| {
+                connection.execute_batch("VACUUM;")?;
+                Ok(())
+            })?;
+
+            return Ok(emit_fan_out(json, "vacuumed", report));
+        }
+        Command::Check {
+            integrity,
+            sample,
+            storage_dir,
+        } => {
+            let report = manager.health_check(HealthCheckOptions {
+                integrity_check: integrity.into(),
+                sample,
+                storage_dir,
+            })?;
+
+            let healthy = report.is_healthy();
+
+            emit(
+                json,
+                json!({
+                    "healthy": healthy,
+                    "master_error": report.master_error,
+                    "missing": report.missing,
+                    "unreadable": report.unreadable,
+                    "corrupted": report.corrupted,
+                    "orphaned": report.orphaned,
+                    "integrity_checked": report.integrity_checked,
+                }),
+                || {
+                    let mut lines = vec![if healthy { "healthy" } else { "unhealthy" }.to_string()];
+
+                    lines.extend(report.master_error.iter().map(|err| format!("master: {}", err)));
+                    lines.extend(report.missing.iter().map(|tenant_id| format!("missing: {}", tenant_id)));
+                    lines.extend(
+                        report
+                            .unreadable
+                            .iter()
+                            .map(|(tenant_id, err)| format!("unreadable: {} ({})", tenant_id, err)),
+                    );
+                    lines.extend(
+                        report
+                            .corrupted
+                            .iter()
+                            .map(|(tenant_id, problems)| format!("corrupted: {} ({})", tenant_id, problems.join("; "))),
+                    );
+                    lines.extend(report.orphaned.iter().map(|path| format!("orphaned: {}", path.display())));
+
+                    lines.join("\n")
+                },
+            );
+
+            if !healthy {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Shell { tenant_id } => {
+            let connection = tenant_connection(&mut manager, &tenant_id)?.connection;
+            shell(&tenant_id, &connection, json)?;
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Prints `value` as JSON, or the text `human` renders.
+fn emit<F: FnOnce() -> String>(json: bool, value: Value, human: F)
+{
+    if json {
+        println!("{}", serde_json::to_string_pretty(&value).unwrap_or_default());
+    } else {
+        println!("{}", human());
+    }
+}
+
+fn emit_fan_out(json: bool, action: &str, report: FanOutReport<()>) -> ExitCode
+{
+    let succeeded: Vec<&str> = report.results.iter().map(|(tenant_id, _)| tenant_id.as_str()).collect();
+    let complete = report.is_complete();
+
+    emit(
+        json,
+        json!({
+            action: succeeded,
+            "failed": report.errors.iter().map(|(tenant_id, err)| json!({
+                "tenant_id": tenant_id,
+                "error": err.to_string(),
+            })).collect::<Vec<_>>(),
+        }),
+        || {
+            let mut lines: Vec<String> = succeeded
+                .iter()
+                .map(|tenant_id| format!("{}: {}", action, tenant_id))
+                .collect();
+            lines.extend(
+                report
+                    .errors
+                    .iter()
+                    .map(|(tenant_id, err)| format!("failed: {} ({})", tenant_id, err)),
+            );
+            lines.join("\n")
+        },
+    );
+
+    if complete {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn tenant_json(tenant: &TenantRecord) -> Value
+{
+    json!({
+        "tenant_id": tenant.tenant_id,
+        "path": tenant.path,
+        "created_at": tenant.created_at,
+    })
+}
+
+fn display_path(path: &Option<PathBuf>) -> String
+{
+    path.as_ref()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "in-memory".to_string())
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String
+{
+    value.map(|value| value.to_string()).unwrap_or_else(|| "-".to_string())
+}
+
+fn tenant_filter(tenants: Vec<String>) -> TenantFilter
+{
+    if tenants.is_empty() {
+        TenantFilter::All
+    } else {
+        TenantFilter::Ids(tenants)
+    }
+}
+
+fn find_tenant(manager: &MultiTenantManager, tenant_id: &str) -> CliResult<TenantRecord>
+{
+    manager
+        .get_tenant(tenant_id)?
+        .ok_or_else(|| MultiTenantError::TenantNotFound(tenant_id.to_string()).into())
+}
+
+/// The file of a tenant, which in-memory tenants do not have.
+fn tenant_path(manager: &MultiTenantManager, tenant_id: &str) -> CliResult<PathBuf>
+{
+    find_tenant(manager, tenant_id)?
+        .path
+        .ok_or_else(|| format!("{} is an in-memory tenant", tenant_id).into())
+}
+
+fn tenant_connection(manager: &mut MultiTenantManager, tenant_id: &str) -> CliResult<TenantConnection>
+{
+    manager
+        .get_connection(tenant_id)?
+        .ok_or_else(|| MultiTenantError::TenantNotFound(tenant_id.to_string()).into())
+}
+
+/// Dumps the schema and rows of a database as SQL that recreates it.
+fn export_sql(connection: &Connection) -> CliResult<String>
+{
+    let mut dump = String::from("BEGIN TRANSACTION;\n");
+
+    let mut statement = connection.prepare(
+        "SELECT type, name, sql FROM sqlite_master WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%' ORDER BY type = \
+         'table' DESC, rowid;",
+    )?;
+    let schema = statement
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    for (kind, name, sql) in schema {
+        dump.push_str(&sql);
+        dump.push_str(";\n");
+
+        if kind != "table" {
+            continue;
+        }
+
+        let mut columns = connection.prepare("SELECT name FROM pragma_table_info(?1);")?;
+        let columns = columns
+            .query_map(params![name], |row| row.get::<_, String>(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        // SQLite's quote() renders every value as a literal, blobs included.
+        let values = columns
+            .iter()
+            .map(|column| format!("quote({})", quote_identifier(column)))
+            .collect::<Vec<_>>()
+            .join(" || ',' || ");
+
+        let mut rows = connection.prepare(&format!(
+            "SELECT 'INSERT INTO ' || ?1 || ' VALUES(' || {} || ');' FROM {};",
+            values,
+            quote_identifier(&name)
+        ))?;
+
+        for insert in rows.query_map(params![quote_identifier(&name)], |row| row.get::<_, String>(0))? {
+            dump.push_str(&insert?);
+            dump.push('\n');
+        }
+    }
+
+    dump.push_str("COMMIT;\n");
+
+    Ok(dump)
+}
+
+/// Reads SQL from stdin until `.quit` or the end of input, running each statement once it ends with `;`.
+fn shell(tenant_id: &str, connection: &Connection, json: bool) -> CliResult<()>
+{
+    let stdin = io::stdin();
+    let mut buffer = String::new();
+
+    eprintln!(
+        "Connected to {}. Enter SQL ending with `;`, `.tables`, or `.quit`.",
+        tenant_id
+    );
+
+    loop {
+        eprint!(
+            "{}",
+            if buffer.is_empty() {
+                format!("{}> ", tenant_id)
+            } else {
+                "...> ".to_string()
+            }
+        );
+        io::stderr().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+
+        if buffer.is_empty() {
+            match line.trim() {
+                "" => continue,
+                ".quit" | ".exit" => break,
+                ".tables" => {
+                    run_statement(
+                        connection,
+                        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name;",
+                        json,
+                    );
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        buffer.push_str(&line);
+
+        if buffer.trim_end().ends_with(';') {
+            run_statement(connection, buffer.trim(), json);
+            buffer.clear();
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs a statement from the shell, printing its rows or the amount of rows it changed. Errors are printed too.
+fn run_statement(connection: &Connection, sql: &str, json: bool)
+{
+    let result = connection.prepare(sql).and_then(|mut statement| {
+        if statement.column_count() == 0 {
+            let changed = statement.raw_execute()?;
+            return Ok(vec![format!("{} rows changed", changed)]);
+        }
+
+        let columns: Vec<String> = statement.column_names().iter().map(|column| column.to_string()).collect();
+        let mut rows = statement.raw_query();
+        let mut lines = Vec::new();
+        let mut objects = Vec::new();
+
+        if !json {
+            lines.push(columns.join("\t"));
+        }
+
+        while let Some(row) = rows.next()? {
+            let values = (0..columns.len())
+                .map(|index| row.get_ref(index).map(value_json))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+
+            if json {
+                objects.push(Value::Object(columns.iter().cloned().zip(values).collect()));
+            } else {
+                lines.push(
+                    values
+                        .iter()
+                        .map(|value| match value {
+                            Value::String(text) => text.clone(),
+                            other => other.to_string(),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\t"),
+                );
+            }
+        }
+
+        if json {
+            lines.push(serde_json::to_string_pretty(&objects).unwrap_or_default());
+        }
+
+        Ok(lines)
+    });
+
+    match result {
+        Ok(lines) => println!("{}", lines.join("\n")),
+        // Several statements can not be prepared at once, so they run as a batch without output.
+        Err(Error::MultipleStatement) => match connection.execute_batch(sql) {
+            Ok(()) => println!("ok"),
+            Err(err) => eprintln!("error: {}", err),
+        },
+        Err(err) => eprintln!("error: {}", err),
+    }
+}
+
+fn value_json(value: ValueRef) -> Value
+{
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(integer) => json!(integer),
+        ValueRef::Real(real) => json!(real),
+        ValueRef::Text(text) => Value::String(String::from_utf8_lossy(text).into_owned()),
+        ValueRef::Blob(blob) => Value::String(format!(
+            "x'{}'",
+            blob.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::path::Path;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn cli(master: &Path, args: &[&str]) -> CliResult<ExitCode>
+    {
+        let master = master.to_str().unwrap();
+        run(Cli::try_parse_from(["sqlite-tenant", "--master", master].iter().chain(args))?)
+    }
+
+    fn tenant_ids(master: &Path) -> Vec<String>
+    {
+        let manager = MultiTenantManager::new(Configuration::builder().master_db_path(master).build().unwrap()).unwrap();
+        manager
+            .list_tenants()
+            .unwrap()
+            .into_iter()
+            .map(|tenant| tenant.tenant_id)
+            .collect()
+    }
+
+    fn set_access_mode(master: &Path, tenant_id: &str, mode: AccessMode)
+    {
+        let mut manager = MultiTenantManager::new(Configuration::builder().master_db_path(master).build().unwrap()).unwrap();
+        manager.set_access_mode(tenant_id, mode).unwrap();
+    }
+
+    #[test]
+    fn test_add_list_and_remove()
+    {
+        let temp_dir = tempdir().unwrap();
+        let master = temp_dir.path().join("master.sqlite");
+        let path = temp_dir.path().join("a.sqlite");
+
+        assert!(cli(&master, &["list"]).is_err());
+        assert!(cli(&master, &["add", "a"]).is_err());
+
+        assert_eq!(
+            cli(&master, &["add", "a", "--path", path.to_str().unwrap()]).unwrap(),
+            ExitCode::SUCCESS
+        );
+        assert_eq!(cli(&master, &["--json", "list"]).unwrap(), ExitCode::SUCCESS);
+        assert_eq!(tenant_ids(&master), vec!["a".to_string()]);
+
+        let sidecars: Vec<PathBuf> = database_files(&path).into_iter().skip(1).collect();
+        for sidecar in &sidecars {
+            fs::write(sidecar, b"").unwrap();
+        }
+
+        assert_eq!(cli(&master, &["remove", "a", "--delete-file"]).unwrap(), ExitCode::SUCCESS);
+        assert!(tenant_ids(&master).is_empty());
+        assert!(!path.exists());
+        assert!(sidecars.iter().all(|sidecar| !sidecar.exists()));
+
+        assert!(cli(&master, &["remove", "a"]).is_err());
+    }
+
+    #[test]
+    fn test_backup_export_and_import()
+    {
+        let temp_dir = tempdir().unwrap();
+        let master = temp_dir.path().join("master.sqlite");
+        let path = |name: &str| temp_dir.path().join(name).to_str().unwrap().to_string();
+
+        cli(&master, &["add", "source", "--path", &path("source.sqlite")]).unwrap();
+        cli(&master, &["add", "copy", "--path", &path("copy.sqlite")]).unwrap();
+
+        Connection::open(path("source.sqlite"))
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE \"odd \"\"name\" (id INTEGER PRIMARY KEY, note TEXT, data BLOB);
+                 INSERT INTO \"odd \"\"name\" VALUES (1, 'it''s', x'00ff');",
+            )
+            .unwrap();
+
+        let rows = |file: &str| -> Vec<(i64, String, Vec<u8>)> {
+            let connection = Connection::open(path(file)).unwrap();
+            let mut statement = connection.prepare("SELECT * FROM \"odd \"\"name\"").unwrap();
+            let rows = statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .unwrap();
+            rows.collect::<std::result::Result<_, _>>().unwrap()
+        };
+
+        cli(&master, &["backup", "source", &path("backup.sqlite")]).unwrap();
+        assert_eq!(rows("backup.sqlite"), vec![(1, "it's".to_string(), vec![0x00, 0xff])]);
+        assert!(cli(&master, &["backup", "source", &path("backup.sqlite")]).is_err());
+
+        cli(&master, &["export", "source", "--output", &path("dump.sql")]).unwrap();
+        cli(&master, &["import", "copy", &path("dump.sql")]).unwrap();
+        assert_eq!(rows("copy.sqlite"), rows("source.sqlite"));
+
+        assert!(cli(&master, &["export", "missing"]).is_err());
+    }
+
+    #[test]
+    fn test_restore()
+    {
+        let temp_dir = tempdir().unwrap();
+        let master = temp_dir.path().join("master.sqlite");
+        let path = |name: &str| temp_dir.path().join(name).to_str().unwrap().to_string();
+        let notes = |file: &str| -> i64 {
+            Connection::open(path(file))
+                .unwrap()
+                .query_row("SELECT COUNT(*) FROM notes", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        cli(&master, &["add", "a", "--path", &path("a.sqlite")]).unwrap();
+        Connection::open(path("a.sqlite"))
+            .unwrap()
+            .execute_batch("CREATE TABLE notes (id INTEGER PRIMARY KEY); INSERT INTO notes DEFAULT VALUES;")
+            .unwrap();
+
+        cli(&master, &["backup", "a", &path("backup.sqlite")]).unwrap();
+        Connection::open(path("a.sqlite"))
+            .unwrap()
+            .execute("INSERT INTO notes DEFAULT VALUES", [])
+            .unwrap();
+
+        set_access_mode(&master, "a", AccessMode::ReadOnly);
+
+        assert!(cli(&master, &["restore", "a", &path("backup.sqlite")]).is_err());
+        assert_eq!(notes("a.sqlite"), 2);
+
+        set_access_mode(&master, "a", AccessMode::ReadWrite);
+
+        assert_eq!(
+            cli(&master, &["restore", "a", &path("backup.sqlite")]).unwrap(),
+            ExitCode::SUCCESS
+        );
+        assert_eq!(notes("a.sqlite"), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_only_master_directory()
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let dir = temp_dir.path().join("readonly");
+        fs::create_dir(&dir).unwrap();
+        let master = dir.join("master.sqlite");
+
+        cli(&master, &["add", "a", "--path", dir.join("a.sqlite").to_str().unwrap()]).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+        // Permission bits do not stop root, there is nothing to check then.
+        if fs::write(dir.join("probe"), b"").is_err() {
+            assert_eq!(cli(&master, &["list"]).unwrap(), ExitCode::SUCCESS);
+            assert_eq!(cli(&master, &["count"]).unwrap(), ExitCode::SUCCESS);
+            assert!(cli(&master, &["add", "b", "--path", dir.join("b.sqlite").to_str().unwrap()]).is_err());
+        }
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
@@ -5,13 +5,15 @@ use sqlite_tenant::prelude::*;
 
 fn main()
 {
-    let mut manager = MultiTenantManager::new(Configuration {
-        master_db_path: Some(PathBuf::new().join("./examples/db/master.sqlite")),
-        log_level: Some(LogLevel::Debug),
-        log_dir: None,
-        lru_cache_cap: Some(5),
-    })
-    .expect("Failed to initialize multi-tenant manager");
+    let _logger = init_logger(LogLevel::Debug, None).expect("Failed to start logger");
+
+    let config = Configuration::builder()
+        .master_db_path("./examples/db/master.sqlite")
+        .lru_cache_cap(5)
+        .build()
+        .expect("Invalid configuration");
+
+    let mut manager = MultiTenantManager::new(config).expect("Failed to initialize multi-tenant manager");
 
     if manager.get_connection("user_db1").unwrap().is_none() {
         manager
@@ -71,7 +73,7 @@ fn add_user(manager: &mut MultiTenantManager, db_name: &str)
             create_user_db(&tenant.connection);
             let conn = &tenant.connection;
 
-            conn.execute("INSERT INTO users (username, email) VALUES (?1, ?2)", &[&username, &email])
+            conn.execute("INSERT INTO users (username, email) VALUES (?1, ?2)", [&username, &email])
                 .expect("Failed to insert user data");
 
             println!("User added to {} database:", db_name);
@@ -91,13 +93,13 @@ fn find_user(manager: &mut MultiTenantManager, db_name: &str)
     match manager.get_connection(db_name) {
         Ok(Some(tenant)) => {
             let conn = &tenant.connection;
-            create_user_db(&conn);
+            create_user_db(conn);
 
             let mut stmt = conn
                 .prepare("SELECT * FROM users WHERE username = ?1")
                 .expect("Failed to prepare statement");
             let user_iter = stmt
-                .query_map(&[&username], |row| {
+                .query_map([&username], |row| {
                     Ok((
                         row.get::<usize, i64>(0)?,    // Assuming the first column is id
                         row.get::<usize, String>(1)?, // Assuming the second column is username
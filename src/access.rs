@@ -0,0 +1,204 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use log::{debug, info, warn};
+use rusqlite::{params, OptionalExtension};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+use crate::statements::SqlStatement;
+use crate::tenant::TenantConnection;
+use crate::{observer, telemetry};
+
+/// Whether a tenant can be written to, as stored in the master database.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AccessMode
+{
+    #[default]
+    ReadWrite,
+    /// Every connection handed out for the tenant is opened with `SQLITE_OPEN_READ_ONLY`, such as for tenants under
+    /// legal hold or being migrated.
+    ReadOnly,
+}
+
+impl AccessMode
+{
+    pub(crate) fn as_str(&self) -> &'static str
+    {
+        match self {
+            AccessMode::ReadWrite => "read_write",
+            AccessMode::ReadOnly => "read_only",
+        }
+    }
+
+    /// Anything but `read_write` is read-only, so an unknown mode never lets writes through.
+    pub(crate) fn from_str(mode: &str) -> Self
+    {
+        match mode {
+            "read_write" => AccessMode::ReadWrite,
+            _ => AccessMode::ReadOnly,
+        }
+    }
+}
+
+impl MultiTenantManager
+{
+    /// Get a read-only connection to a tenant, which fails to run any statement that writes.
+    ///
+    /// Read-only connections are cached apart from read-write ones, in an LRU cache with the same capacity. In-memory
+    /// tenants have no file to open again, so they can not be opened read-only.
+    pub fn get_connection_readonly(&mut self, tenant_id: &str) -> SQLResult<Option<TenantConnection>, MultiTenantError>
+    {
+        match self.fetch_readonly_connection(tenant_id)? {
+            Some(connection) => {
+                self.check_connection_quota(tenant_id, &connection)?;
+                self.record_access(tenant_id);
+                Ok(Some(connection))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Gets the access mode of a tenant.
+    pub fn access_mode(&self, tenant_id: &str) -> SQLResult<AccessMode, MultiTenantError>
+    {
+        let mode: Option<String> = self
+            .master_db
+            .query_row(SqlStatement::SelectAccessMode.as_str(), params![tenant_id], |row| row.get(0))
+            .optional()?;
+
+        mode.map(|mode| AccessMode::from_str(&mode))
+            .ok_or_else(|| MultiTenantError::TenantNotFound(tenant_id.to_string()))
+    }
+
+    /// Sets the access mode of a tenant. While a tenant is `ReadOnly`, `get_connection` and fan-out queries hand out
+    /// read-only connections.
+    ///
+    /// The cached read-write connection is dropped right away, but callers still holding it can keep writing until
+    /// they drop it.
+    pub fn set_access_mode(&mut self, tenant_id: &str, mode: AccessMode) -> SQLResult<(), MultiTenantError>
+    {
+        let tenant = self
+            .get_tenant(tenant_id)?
+            .ok_or_else(|| MultiTenantError::TenantNotFound(tenant_id.to_string()))?;
+
+        if mode == AccessMode::ReadOnly && tenant.path.is_none() {
+            return Err(MultiTenantError::DatabaseError(format!(
+                "Tenant '{}' is in-memory and can not be made read-only",
+                tenant_id
+            )));
+        }
+
+        observer::veto(&self.observers, |observer| {
+            observer.before_access_mode_change(tenant_id, mode)
+        })?;
+
+        self.master_db
+            .execute(SqlStatement::UpdateAccessMode.as_str(), params![tenant_id, mode.as_str()])?;
+
+        match mode {
+            AccessMode::ReadOnly => {
+                self.read_only_tenants.insert(tenant_id.to_string());
+
+                if let Some(connection) = self.cache.remove(tenant_id) {
+                    self.detach_or_close(tenant_id, connection);
+                }
+            }
+            AccessMode::ReadWrite => {
+                self.read_only_tenants.remove(tenant_id);
+            }
+        }
+
+        info!("Set ({}) tenant access mode to {}.", tenant_id, mode.as_str());
+
+        observer::notify(&self.observers, "access mode change", tenant_id, |observer| {
+            observer.on_access_mode_changed(tenant_id, mode)
+        });
+
+        Ok(())
+    }
+
+    /// Gets a read-only connection from its cache, or opens one, without checking quotas.
+    pub(crate) fn fetch_readonly_connection(
+        &mut self,
+        tenant_id: &str,
+    ) -> SQLResult<Option<TenantConnection>, MultiTenantError>
+    {
+        if let Some(connection) = self.readonly_cache.get(tenant_id) {
+            debug!("Retrieving ({}) read-only sqlite connection from cache.", tenant_id);
+            let connection = connection.clone();
+            self.stats.hits += 1;
+            telemetry::cache_hit();
+            self.last_access.insert(tenant_id.to_string(), Instant::now());
+            return Ok(Some(connection));
+        }
+
+        self.stats.misses += 1;
+        telemetry::cache_miss(tenant_id);
+
+        let Some(tenant) = self.get_tenant(tenant_id)? else {
+            warn!("Tenant ({}) not found in database.", tenant_id);
+            return Ok(None);
+        };
+
+        let Some(path) = tenant.path else {
+            return Err(MultiTenantError::DatabaseError(format!(
+                "Tenant '{}' is in-memory and can not be opened read-only",
+                tenant_id
+            )));
+        };
+
+        self.reserve_readonly_slot(tenant_id)?;
+        self.reserve_connection()?;
+
+        let connection = self.open_tenant_file(tenant_id, &path, |path| TenantConnection::open_read_only(path))?;
+        self.apply_quotas(tenant_id, &connection)?;
+        self.cache_readonly_connection(tenant_id, connection.clone());
+
+        debug!("Opened ({}) read-only sqlite connection.", tenant_id);
+
+        Ok(Some(connection))
+    }
+
+    /// Adds a read-only connection to its cache. Callers reserve room for it with `reserve_readonly_slot` first.
+    fn cache_readonly_connection(&mut self, tenant_id: &str, connection: TenantConnection)
+    {
+        self.prune_detached();
+
+        self.last_access.insert(tenant_id.to_string(), Instant::now());
+        self.record_open(tenant_id);
+
+        if let Err(err) = self.install_query_trace(tenant_id, &connection.connection) {
+            warn!("Failed to trace queries of ({}) tenant: {}", tenant_id, err);
+        }
+
+        self.readonly_cache.insert(tenant_id.to_string(), connection);
+
+        telemetry::tenant_opened(tenant_id);
+        observer::notify(&self.observers, "open", tenant_id, |observer| {
+            observer.on_opened(tenant_id, true)
+        });
+        self.report_open_connections();
+    }
+
+    /// Drops the cached read-only connection of a tenant, if it has one.
+    pub(crate) fn drop_readonly_connection(&mut self, tenant_id: &str)
+    {
+        if let Some(connection) = self.readonly_cache.remove(tenant_id) {
+            self.detach_or_close(tenant_id, connection);
+        }
+    }
+
+    /// Tracks a connection taken out of a cache until its last caller drops it, or closes it if nobody holds it.
+    fn detach_or_close(&mut self, tenant_id: &str, connection: TenantConnection)
+    {
+        if Arc::strong_count(&connection.connection) > 1 {
+            self.detached
+                .push((tenant_id.to_string(), Arc::downgrade(&connection.connection)));
+        } else {
+            telemetry::tenants_closed(1);
+        }
+
+        self.report_open_connections();
+    }
+}
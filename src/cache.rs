@@ -0,0 +1,708 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use lru::LruCache;
+use rusqlite::{params, Connection};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+use crate::observer::{self, EvictionReason};
+use crate::statements::SqlStatement;
+use crate::telemetry;
+use crate::tenant::TenantConnection;
+
+/// The eviction policy used by the manager's connection cache.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum CachePolicy
+{
+    /// Evicts the least recently used tenant.
+    #[default]
+    Lru,
+    /// Evicts the least frequently used tenant. Access counts outlive eviction and decay over time (TinyLFU style),
+    /// so a burst of long tail tenants evicts itself instead of the hot ones.
+    Lfu,
+    /// Evicts tenants idle for longer than the given duration first, then the least recently used.
+    Ttl(Duration),
+    /// Never evicts the listed tenants, and uses `policy` for everything else.
+    Pinned
+    {
+        tenants: Vec<String>, policy: Box<CachePolicy>
+    },
+}
+
+impl CachePolicy
+{
+    /// Builds an empty cache for this policy holding up to `capacity` evictable connections.
+    pub fn build(&self, capacity: usize) -> Box<dyn TenantCache>
+    {
+        match self {
+            CachePolicy::Lru => Box::new(LruTenantCache::new(capacity)),
+            CachePolicy::Lfu => Box::new(LfuTenantCache::new(capacity)),
+            CachePolicy::Ttl(ttl) => Box::new(TtlTenantCache::new(capacity, *ttl)),
+            CachePolicy::Pinned { tenants, policy } => {
+                Box::new(PinnedTenantCache::new(tenants.iter().cloned(), policy.build(capacity)))
+            }
+        }
+    }
+}
+
+/// Storage for cached tenant connections.
+///
+/// A cache only decides *which* tenant goes next through `eviction_order`. The manager does the evicting, since it
+/// knows which connections are still borrowed, so `insert` must never drop entries on its own.
+pub trait TenantCache
+{
+    /// Gets a connection and records the access.
+    fn get(&mut self, tenant_id: &str) -> Option<&TenantConnection>;
+
+    /// Gets a connection without recording the access.
+    fn peek(&self, tenant_id: &str) -> Option<&TenantConnection>;
+
+    fn insert(&mut self, tenant_id: String, connection: TenantConnection);
+
+    fn remove(&mut self, tenant_id: &str) -> Option<TenantConnection>;
+
+    /// The amount of cached connections, pinned ones included.
+    fn len(&self) -> usize;
+
+    /// The amount of cached connections that count against `capacity`, pinned ones excluded.
+    fn evictable_len(&self) -> usize
+    {
+        self.len()
+    }
+
+    /// The max amount of evictable connections.
+    fn capacity(&self) -> usize;
+
+    /// Evictable tenant ids, the first being the next one to evict.
+    fn eviction_order(&self) -> Vec<String>;
+
+    /// Tenants the policy wants gone regardless of how full the cache is.
+    fn expired(&self) -> Vec<String>
+    {
+        Vec::new()
+    }
+
+    /// Stops a tenant from ever being evicted. Returns `false` if the policy does not support pinning.
+    fn pin(&mut self, _tenant_id: &str) -> bool
+    {
+        false
+    }
+
+    /// Lets a pinned tenant be evicted again. Returns `false` if it was not pinned.
+    fn unpin(&mut self, _tenant_id: &str) -> bool
+    {
+        false
+    }
+
+    fn contains(&self, tenant_id: &str) -> bool
+    {
+        self.peek(tenant_id).is_some()
+    }
+
+    fn is_empty(&self) -> bool
+    {
+        self.len() == 0
+    }
+
+    /// Returns `true` if inserting a new tenant requires evicting one first.
+    fn is_full(&self) -> bool
+    {
+        self.evictable_len() >= self.capacity()
+    }
+}
+
+/// Least recently used eviction.
+pub struct LruTenantCache
+{
+    entries: LruCache<String, TenantConnection>,
+    capacity: usize,
+}
+
+impl LruTenantCache
+{
+    pub fn new(capacity: usize) -> Self
+    {
+        Self {
+            entries: LruCache::unbounded(),
+            capacity,
+        }
+    }
+}
+
+impl TenantCache for LruTenantCache
+{
+    fn get(&mut self, tenant_id: &str) -> Option<&TenantConnection>
+    {
+        self.entries.get(tenant_id)
+    }
+
+    fn peek(&self, tenant_id: &str) -> Option<&TenantConnection>
+    {
+        self.entries.peek(tenant_id)
+    }
+
+    fn insert(&mut self, tenant_id: String, connection: TenantConnection)
+    {
+        self.entries.put(tenant_id, connection);
+    }
+
+    fn remove(&mut self, tenant_id: &str) -> Option<TenantConnection>
+    {
+        self.entries.pop(tenant_id)
+    }
+
+    fn len(&self) -> usize
+    {
+        self.entries.len()
+    }
+
+    fn capacity(&self) -> usize
+    {
+        self.capacity
+    }
+
+    fn eviction_order(&self) -> Vec<String>
+    {
+        self.entries.iter().rev().map(|(tenant_id, _)| tenant_id.clone()).collect()
+    }
+}
+
+/// Least frequently used eviction with TinyLFU style aging.
+///
+/// Access counts are kept for tenants that are not cached too, and are halved every `10 * capacity` accesses so old
+/// popularity fades out.
+pub struct LfuTenantCache
+{
+    entries: HashMap<String, (TenantConnection, u64)>,
+    frequencies: HashMap<String, u32>,
+    capacity: usize,
+    /// Incremented on every access, used to break frequency ties by recency.
+    clock: u64,
+    accesses_since_aging: usize,
+}
+
+impl LfuTenantCache
+{
+    pub fn new(capacity: usize) -> Self
+    {
+        Self {
+            entries: HashMap::new(),
+            frequencies: HashMap::new(),
+            capacity,
+            clock: 0,
+            accesses_since_aging: 0,
+        }
+    }
+
+    fn record_access(&mut self, tenant_id: &str)
+    {
+        self.clock += 1;
+        *self.frequencies.entry(tenant_id.to_string()).or_default() += 1;
+
+        self.accesses_since_aging += 1;
+
+        if self.accesses_since_aging >= self.capacity.max(1) * 10 {
+            self.accesses_since_aging = 0;
+            self.frequencies.values_mut().for_each(|frequency| *frequency /= 2);
+            self.frequencies.retain(|_, frequency| *frequency > 0);
+        }
+    }
+
+    fn frequency(&self, tenant_id: &str) -> u32
+    {
+        self.frequencies.get(tenant_id).copied().unwrap_or_default()
+    }
+}
+
+impl TenantCache for LfuTenantCache
+{
+    fn get(&mut self, tenant_id: &str) -> Option<&TenantConnection>
+    {
+        if !self.entries.contains_key(tenant_id) {
+            return None;
+        }
+
+        self.record_access(tenant_id);
+
+        let clock = self.clock;
+        self.entries.get_mut(tenant_id).map(|(connection, last_access)| {
+            *last_access = clock;
+            &*connection
+        })
+    }
+
+    fn peek(&self, tenant_id: &str) -> Option<&TenantConnection>
+    {
+        self.entries.get(tenant_id).map(|(connection, _)| connection)
+    }
+
+    fn insert(&mut self, tenant_id: String, connection: TenantConnection)
+    {
+        self.record_access(&tenant_id);
+        self.entries.insert(tenant_id, (connection, self.clock));
+    }
+
+    fn remove(&mut self, tenant_id: &str) -> Option<TenantConnection>
+    {
+        self.entries.remove(tenant_id).map(|(connection, _)| connection)
+    }
+
+    fn len(&self) -> usize
+    {
+        self.entries.len()
+    }
+
+    fn capacity(&self) -> usize
+    {
+        self.capacity
+    }
+
+    fn eviction_order(&self) -> Vec<String>
+    {
+        let mut order: Vec<(&String, u32, u64)> = self
+            .entries
+            .iter()
+            .map(|(tenant_id, (_, last_access))| (tenant_id, self.frequency(tenant_id), *last_access))
+            .collect();
+
+        order.sort_by_key(|(_, frequency, last_access)| (*frequency, *last_access));
+        order.into_iter().map(|(tenant_id, _, _)| tenant_id.clone()).collect()
+    }
+}
+
+/// Expires tenants that have been idle for longer than `ttl`, otherwise evicts the least recently used.
+pub struct TtlTenantCache
+{
+    entries: HashMap<String, (TenantConnection, Instant)>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl TtlTenantCache
+{
+    pub fn new(capacity: usize, ttl: Duration) -> Self
+    {
+        Self {
+            entries: HashMap::new(),
+            capacity,
+            ttl,
+        }
+    }
+}
+
+impl TenantCache for TtlTenantCache
+{
+    fn get(&mut self, tenant_id: &str) -> Option<&TenantConnection>
+    {
+        self.entries.get_mut(tenant_id).map(|(connection, last_access)| {
+            *last_access = Instant::now();
+            &*connection
+        })
+    }
+
+    fn peek(&self, tenant_id: &str) -> Option<&TenantConnection>
+    {
+        self.entries.get(tenant_id).map(|(connection, _)| connection)
+    }
+
+    fn insert(&mut self, tenant_id: String, connection: TenantConnection)
+    {
+        self.entries.insert(tenant_id, (connection, Instant::now()));
+    }
+
+    fn remove(&mut self, tenant_id: &str) -> Option<TenantConnection>
+    {
+        self.entries.remove(tenant_id).map(|(connection, _)| connection)
+    }
+
+    fn len(&self) -> usize
+    {
+        self.entries.len()
+    }
+
+    fn capacity(&self) -> usize
+    {
+        self.capacity
+    }
+
+    fn eviction_order(&self) -> Vec<String>
+    {
+        let mut order: Vec<(&String, Instant)> = self
+            .entries
+            .iter()
+            .map(|(tenant_id, (_, last_access))| (tenant_id, *last_access))
+            .collect();
+
+        order.sort_by_key(|(_, last_access)| *last_access);
+        order.into_iter().map(|(tenant_id, _)| tenant_id.clone()).collect()
+    }
+
+    fn expired(&self) -> Vec<String>
+    {
+        self.entries
+            .iter()
+            .filter(|(_, (_, last_access))| last_access.elapsed() > self.ttl)
+            .map(|(tenant_id, _)| tenant_id.clone())
+            .collect()
+    }
+}
+
+/// Keeps pinned tenants out of reach of eviction, and hands every other tenant to an inner policy.
+///
+/// Pinned tenants do not count against the capacity of the inner policy.
+pub struct PinnedTenantCache
+{
+    pinned: HashSet<String>,
+    pinned_entries: HashMap<String, TenantConnection>,
+    inner: Box<dyn TenantCache>,
+}
+
+impl PinnedTenantCache
+{
+    pub fn new<I: IntoIterator<Item = String>>(pinned: I, inner: Box<dyn TenantCache>) -> Self
+    {
+        Self {
+            pinned: pinned.into_iter().collect(),
+            pinned_entries: HashMap::new(),
+            inner,
+        }
+    }
+}
+
+impl TenantCache for PinnedTenantCache
+{
+    fn get(&mut self, tenant_id: &str) -> Option<&TenantConnection>
+    {
+        match self.pinned_entries.get(tenant_id) {
+            Some(connection) => Some(connection),
+            None => self.inner.get(tenant_id),
+        }
+    }
+
+    fn peek(&self, tenant_id: &str) -> Option<&TenantConnection>
+    {
+        self.pinned_entries.get(tenant_id).or_else(|| self.inner.peek(tenant_id))
+    }
+
+    fn insert(&mut self, tenant_id: String, connection: TenantConnection)
+    {
+        if self.pinned.contains(&tenant_id) {
+            self.pinned_entries.insert(tenant_id, connection);
+        } else {
+            self.inner.insert(tenant_id, connection);
+        }
+    }
+
+    fn remove(&mut self, tenant_id: &str) -> Option<TenantConnection>
+    {
+        self.pinned_entries.remove(tenant_id).or_else(|| self.inner.remove(tenant_id))
+    }
+
+    fn len(&self) -> usize
+    {
+        self.pinned_entries.len() + self.inner.len()
+    }
+
+    fn evictable_len(&self) -> usize
+    {
+        self.inner.evictable_len()
+    }
+
+    fn capacity(&self) -> usize
+    {
+        self.inner.capacity()
+    }
+
+    fn eviction_order(&self) -> Vec<String>
+    {
+        self.inner.eviction_order()
+    }
+
+    fn expired(&self) -> Vec<String>
+    {
+        self.inner.expired()
+    }
+
+    fn pin(&mut self, tenant_id: &str) -> bool
+    {
+        if let Some(connection) = self.inner.remove(tenant_id) {
+            self.pinned_entries.insert(tenant_id.to_string(), connection);
+        }
+
+        self.pinned.insert(tenant_id.to_string());
+        true
+    }
+
+    fn unpin(&mut self, tenant_id: &str) -> bool
+    {
+        if let Some(connection) = self.pinned_entries.remove(tenant_id) {
+            self.inner.insert(tenant_id.to_string(), connection);
+        }
+
+        self.pinned.remove(tenant_id)
+    }
+}
+
+/// Counters describing how the connection cache is behaving.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheStats
+{
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Connections currently in the cache.
+    pub cached: usize,
+    /// Connections evicted from the cache that are still open because a caller holds them.
+    pub detached: usize,
+    /// Every open tenant connection, `cached + detached`.
+    pub open_connections: usize,
+    pub max_open_connections: Option<usize>,
+}
+
+/// Returns `true` if only the cache holds this connection.
+fn is_idle(connection: &TenantConnection) -> bool
+{
+    Arc::strong_count(&connection.connection) == 1
+}
+
+impl MultiTenantManager
+{
+    /// Gets the cache counters along with the amount of open connections.
+    pub fn cache_stats(&mut self) -> CacheStats
+    {
+        self.prune_detached();
+
+        CacheStats {
+            cached: self.cache.len() + self.readonly_cache.len(),
+            detached: self.detached.len(),
+            open_connections: self.open_connection_count(),
+            max_open_connections: self.max_open_connections,
+            ..self.stats.clone()
+        }
+    }
+
+    /// Makes room for one more open connection under `max_open_connections`.
+    ///
+    /// Idle cached connections are closed, least recently used first. If every open connection is borrowed a
+    /// `ConnectionLimitReached` error is returned instead of going over the limit.
+    pub(crate) fn reserve_connection(&mut self) -> SQLResult<(), MultiTenantError>
+    {
+        let Some(max_open) = self.max_open_connections else {
+            return Ok(());
+        };
+
+        self.prune_detached();
+
+        while self.open_connection_count() >= max_open {
+            if !self.evict_idle() && !self.evict_idle_readonly() {
+                warn!("All {} open tenant connections are in use.", max_open);
+                return Err(MultiTenantError::ConnectionLimitReached(format!(
+                    "all {} open connections are in use",
+                    max_open
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes room in the cache for `tenant_id`, closing idle cached connections least recently used first.
+    ///
+    /// Borrowed connections are never pushed out of the cache. If every cached connection is in use a
+    /// `ConnectionLimitReached` error is returned instead.
+    pub(crate) fn reserve_cache_slot(&mut self, tenant_id: &str) -> SQLResult<(), MultiTenantError>
+    {
+        self.prune_detached();
+        self.evict_expired();
+
+        while self.cache.is_full() && !self.cache.contains(tenant_id) {
+            if !self.evict_idle() {
+                warn!("All {} cached tenant connections are in use.", self.cache.capacity());
+                return Err(MultiTenantError::ConnectionLimitReached(format!(
+                    "all {} cached connections are in use",
+                    self.cache.capacity()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes room in the read-only cache for `tenant_id`, like `reserve_cache_slot`.
+    pub(crate) fn reserve_readonly_slot(&mut self, tenant_id: &str) -> SQLResult<(), MultiTenantError>
+    {
+        self.prune_detached();
+
+        while self.readonly_cache.is_full() && !self.readonly_cache.contains(tenant_id) {
+            if !self.evict_idle_readonly() {
+                warn!(
+                    "All {} cached read-only tenant connections are in use.",
+                    self.readonly_cache.capacity()
+                );
+                return Err(MultiTenantError::ConnectionLimitReached(format!(
+                    "all {} cached read-only connections are in use",
+                    self.readonly_cache.capacity()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a connection to the cache. Callers reserve room for it with `reserve_cache_slot` first.
+    pub(crate) fn cache_connection(&mut self, tenant_id: &str, connection: TenantConnection)
+    {
+        self.prune_detached();
+
+        self.last_access.insert(tenant_id.to_string(), Instant::now());
+        self.record_open(tenant_id);
+
+        if let Err(err) = self.install_query_trace(tenant_id, &connection.connection) {
+            warn!("Failed to trace queries of ({}) tenant: {}", tenant_id, err);
+        }
+
+        let read_only = connection.is_read_only();
+        self.cache.insert(tenant_id.to_string(), connection);
+
+        telemetry::tenant_opened(tenant_id);
+        observer::notify(&self.observers, "open", tenant_id, |observer| {
+            observer.on_opened(tenant_id, read_only)
+        });
+        self.report_open_connections();
+    }
+
+    /// Pins a tenant so the cache never evicts it, and flags it as pinned in the master database.
+    ///
+    /// The flag is kept even if the cache policy does not support pinning, so `WarmupStrategy::Pinned` still loads it.
+    pub fn pin_tenant(&mut self, tenant_id: &str) -> SQLResult<(), MultiTenantError>
+    {
+        self.set_pinned(tenant_id, true)?;
+
+        if !self.cache.pin(tenant_id) {
+            warn!(
+                "Cache policy does not support pinning, ({}) tenant can still be evicted.",
+                tenant_id
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Lets a pinned tenant be evicted again.
+    pub fn unpin_tenant(&mut self, tenant_id: &str) -> SQLResult<(), MultiTenantError>
+    {
+        self.set_pinned(tenant_id, false)?;
+        self.cache.unpin(tenant_id);
+
+        Ok(())
+    }
+
+    fn set_pinned(&mut self, tenant_id: &str, pinned: bool) -> SQLResult<(), MultiTenantError>
+    {
+        let updated = self
+            .master_db
+            .execute(SqlStatement::UpdatePinned.as_str(), params![tenant_id, pinned])?;
+
+        if updated == 0 {
+            return Err(MultiTenantError::TenantNotFound(tenant_id.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Closes the next idle connection in the eviction order. Returns `false` if there is none.
+    fn evict_idle(&mut self) -> bool
+    {
+        let Some(tenant_id) = self
+            .cache
+            .eviction_order()
+            .into_iter()
+            .find(|tenant_id| self.cache.peek(tenant_id).is_some_and(is_idle))
+        else {
+            return false;
+        };
+
+        self.cache.remove(&tenant_id);
+        self.record_eviction(&tenant_id, EvictionReason::Idle);
+        telemetry::tenants_closed(1);
+        self.report_open_connections();
+
+        debug!("Evicted ({}) tenant from cache and closed its connection.", tenant_id);
+
+        true
+    }
+
+    /// Closes the least recently used idle read-only connection. Returns `false` if there is none.
+    pub(crate) fn evict_idle_readonly(&mut self) -> bool
+    {
+        let Some(tenant_id) = self
+            .readonly_cache
+            .eviction_order()
+            .into_iter()
+            .find(|tenant_id| self.readonly_cache.peek(tenant_id).is_some_and(is_idle))
+        else {
+            return false;
+        };
+
+        self.readonly_cache.remove(&tenant_id);
+        self.record_eviction(&tenant_id, EvictionReason::Idle);
+        telemetry::tenants_closed(1);
+        self.report_open_connections();
+
+        debug!(
+            "Evicted ({}) read-only tenant from cache and closed its connection.",
+            tenant_id
+        );
+
+        true
+    }
+
+    /// Closes idle connections the cache policy has expired, returning their tenants.
+    pub(crate) fn evict_expired(&mut self) -> Vec<String>
+    {
+        let mut expired = Vec::new();
+
+        for tenant_id in self.cache.expired() {
+            if self.cache.peek(&tenant_id).is_some_and(is_idle) {
+                self.cache.remove(&tenant_id);
+                self.record_eviction(&tenant_id, EvictionReason::Expired);
+                telemetry::tenants_closed(1);
+                self.report_open_connections();
+
+                debug!("Expired ({}) tenant from cache and closed its connection.", tenant_id);
+                expired.push(tenant_id);
+            }
+        }
+
+        expired
+    }
+
+    /// Forgets detached connections that every caller has dropped.
+    pub(crate) fn prune_detached(&mut self)
+    {
+        let detached = self.detached.len();
+
+        self.detached
+            .retain(|(_, connection): &(String, Weak<Connection>)| connection.strong_count() > 0);
+
+        if self.detached.len() < detached {
+            telemetry::tenants_closed(detached - self.detached.len());
+            self.report_open_connections();
+        }
+    }
+
+    /// Reports the amount of open connections to the metrics recorder.
+    pub(crate) fn report_open_connections(&self)
+    {
+        telemetry::open_connections(self.open_connection_count());
+    }
+
+    /// Cached read-write and read-only connections, along with detached ones.
+    fn open_connection_count(&self) -> usize
+    {
+        self.cache.len() + self.readonly_cache.len() + self.detached.len()
+    }
+}
@@ -1,19 +1,405 @@
-use std::path::PathBuf;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{env, fs};
 
-use crate::logger::LogLevel;
+use log::warn;
+
+use crate::cache::CachePolicy;
+use crate::error::MultiTenantError;
+use crate::query_trace::QueryTraceOptions;
+use crate::recovery::MissingFilePolicy;
+
+/// Every key `ConfigurationBuilder::set` accepts.
+const CONFIGURATION_KEYS: [&str; 11] = [
+    "master_db_path",
+    "lru_cache_cap",
+    "max_open_connections",
+    "cache_policy",
+    "cache_ttl_secs",
+    "idle_timeout_secs",
+    "stats_flush_interval_secs",
+    "slow_query_threshold_ms",
+    "recent_queries",
+    "missing_file_policy",
+    "backup_dir",
+];
+
+/// The cache cap used when `Configuration::lru_cache_cap` is `None`.
+pub(crate) const DEFAULT_CACHE_CAP: usize = 150;
+/// The flush interval used when `Configuration::stats_flush_interval` is `None`.
+pub(crate) const DEFAULT_STATS_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
 
 /// The config for the tenant manager.
+///
+/// Besides building it in code, it can be loaded with `from_toml` (behind the `toml` feature) or `from_env`, which
+/// both accept the same keys:
+///
+/// | key | value |
+/// | --- | --- |
+/// | `master_db_path` | path to the master database |
+/// | `lru_cache_cap` | max cached connections |
+/// | `max_open_connections` | max open connections |
+/// | `cache_policy` | `lru`, `lfu` or `ttl` |
+/// | `cache_ttl_secs` | idle time before the `ttl` policy evicts a tenant |
+/// | `idle_timeout_secs` | idle time before `tick` closes a connection |
+/// | `stats_flush_interval_secs` | how often access stats are written |
+/// | `slow_query_threshold_ms` | traces every tenant, logging slower statements |
+/// | `recent_queries` | traces every tenant, keeping this many statements |
+/// | `missing_file_policy` | `error`, `recreate` or `restore` |
+/// | `backup_dir` | where the `restore` policy looks for backups |
 #[derive(Clone)]
 pub struct Configuration
 {
     /// The path to the sqlite master database that controls the library storage
     pub master_db_path: Option<PathBuf>,
-    /// The log level used in the program. If `None` is passed, logging is disabled.
-    pub log_level: Option<LogLevel>,
-    /// The directory logs will be written to, if `None` it will default to 'logs' in your project root.
-    pub log_dir: Option<PathBuf>,
-    /// The max captivity of connections to hold for the database manager.
+    /// The max captivity of connections to hold for the database manager, whatever the cache policy.
     /// If `None` is provided, the cache will default to 150.
     /// https://en.wikipedia.org/wiki/Cache_replacement_policies
     pub lru_cache_cap: Option<usize>,
+    /// The max amount of tenant connections open at once, including connections evicted from the cache that a
+    /// caller still holds. If `None` is provided, only the cache cap limits open connections.
+    ///
+    /// Connections opened for a single fan-out or federated query are not counted.
+    pub max_open_connections: Option<usize>,
+    /// How the cache picks which tenant to evict. If `None` is provided, it defaults to `CachePolicy::Lru`.
+    pub cache_policy: Option<CachePolicy>,
+    /// How long a cached connection can go unused before `MultiTenantManager::tick` closes it.
+    /// If `None` is provided, idle connections are left open.
+    pub idle_timeout: Option<Duration>,
+    /// How often tenant access stats are written to the master database.
+    /// If `None` is provided, it defaults to 30 seconds.
+    pub stats_flush_interval: Option<Duration>,
+    /// Traces the statements of every tenant, see `MultiTenantManager::trace_queries` to trace a single one.
+    /// If `None` is provided, statements are not traced.
+    pub query_trace: Option<QueryTraceOptions>,
+    /// What to do when the file of a registered tenant is missing as it is opened.
+    /// If `None` is provided, it defaults to `MissingFilePolicy::Error`.
+    pub missing_file_policy: Option<MissingFilePolicy>,
+}
+
+impl Default for Configuration
+{
+    /// An in-memory master with a 150 connection LRU cache, flushing access stats every 30 seconds.
+    fn default() -> Self
+    {
+        Self {
+            master_db_path: None,
+            lru_cache_cap: Some(DEFAULT_CACHE_CAP),
+            max_open_connections: None,
+            cache_policy: Some(CachePolicy::Lru),
+            idle_timeout: None,
+            stats_flush_interval: Some(DEFAULT_STATS_FLUSH_INTERVAL),
+            query_trace: None,
+            missing_file_policy: None,
+        }
+    }
+}
+
+impl Configuration
+{
+    pub fn builder() -> ConfigurationBuilder
+    {
+        ConfigurationBuilder::default()
+    }
+
+    /// Loads the configuration from a TOML file, using the defaults for missing keys.
+    #[cfg(feature = "toml")]
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> Result<Self, MultiTenantError>
+    {
+        let path = path.as_ref();
+
+        let contents = fs::read_to_string(path)
+            .map_err(|err| MultiTenantError::InvalidConfiguration(format!("failed to read {}: {}", path.display(), err)))?;
+
+        let table: toml::Table = contents
+            .parse()
+            .map_err(|err| MultiTenantError::InvalidConfiguration(format!("failed to parse {}: {}", path.display(), err)))?;
+
+        let mut builder = Self::builder();
+
+        for (key, value) in table {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                other => {
+                    return Err(MultiTenantError::InvalidConfiguration(format!(
+                        "`{}` can not be a {}",
+                        key,
+                        other.type_str()
+                    )))
+                }
+            };
+
+            builder = builder.set(&key, &value)?;
+        }
+
+        builder.build()
+    }
+
+    /// Loads the configuration from environment variables named `{prefix}_{KEY}`, such as `TENANT_LRU_CACHE_CAP`,
+    /// using the defaults for variables that are not set.
+    ///
+    /// Variables with the prefix that do not name a configuration key are logged and ignored, since other tools may
+    /// share the prefix. Invalid values of known keys still fail the load.
+    pub fn from_env(prefix: &str) -> Result<Self, MultiTenantError>
+    {
+        let prefix = format!("{}_", prefix.to_uppercase());
+        let mut builder = Self::builder();
+
+        // Variables that are not valid unicode are skipped, `env::vars` would panic on them.
+        let vars = env::vars_os().filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(&prefix).map(str::to_lowercase) else {
+                continue;
+            };
+
+            if !CONFIGURATION_KEYS.contains(&key.as_str()) {
+                warn!("Ignoring {}, `{}` is not a configuration key.", name, key);
+                continue;
+            }
+
+            builder = builder.set(&key, &value)?;
+        }
+
+        builder.build()
+    }
+
+    /// Checks that the cache cap, connection limit and cache TTL are non-zero, that the restore policy has a backup
+    /// directory, and that the directory of the master database exists and is writable.
+    pub fn validate(&self) -> Result<(), MultiTenantError>
+    {
+        if self.cache_policy == Some(CachePolicy::Ttl(Duration::ZERO)) {
+            return Err(MultiTenantError::InvalidConfiguration(
+                "the ttl cache_policy needs a cache_ttl_secs greater than 0".to_string(),
+            ));
+        }
+
+        if self.lru_cache_cap == Some(0) {
+            return Err(MultiTenantError::InvalidConfiguration(
+                "lru_cache_cap must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.max_open_connections == Some(0) {
+            return Err(MultiTenantError::InvalidConfiguration(
+                "max_open_connections must be greater than 0".to_string(),
+            ));
+        }
+
+        if let Some(MissingFilePolicy::RestoreLatestBackup(backup_dir)) = &self.missing_file_policy {
+            if backup_dir.as_os_str().is_empty() {
+                return Err(MultiTenantError::InvalidConfiguration(
+                    "the restore missing_file_policy needs a backup_dir".to_string(),
+                ));
+            }
+        }
+
+        if let Some(path) = &self.master_db_path {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+
+            let metadata = fs::metadata(dir).map_err(|err| {
+                MultiTenantError::InvalidConfiguration(format!("master database directory {}: {}", dir.display(), err))
+            })?;
+
+            if !metadata.is_dir() {
+                return Err(MultiTenantError::InvalidConfiguration(format!(
+                    "{} is not a directory",
+                    dir.display()
+                )));
+            }
+
+            check_writable(dir)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that files can be created in `dir` by creating and deleting an empty one, since permission bits do not
+/// tell whether the current user may write to it.
+///
+/// Every probe gets a name of its own, so a probe left behind by a crash or made by another thread at the same time is
+/// skipped rather than taken for an unwritable directory.
+fn check_writable(dir: &Path) -> Result<(), MultiTenantError>
+{
+    static NEXT_PROBE: AtomicU64 = AtomicU64::new(0);
+
+    loop {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let probe = dir.join(format!(
+            ".sqlite-tenant-write-check-{}-{}-{}",
+            std::process::id(),
+            nanos,
+            NEXT_PROBE.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        match fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+            Ok(_) => {
+                let _ = fs::remove_file(&probe);
+                return Ok(());
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(MultiTenantError::InvalidConfiguration(format!(
+                    "master database directory {} is not writable: {}",
+                    dir.display(),
+                    err
+                )))
+            }
+        }
+    }
+}
+
+/// Builds a `Configuration` on top of the defaults, validating it once it is built.
+#[derive(Clone, Default)]
+pub struct ConfigurationBuilder
+{
+    config: Configuration,
+    /// `cache_ttl_secs`, kept apart from `config` since it may be set before the `ttl` policy.
+    cache_ttl: Option<Duration>,
+    /// `backup_dir`, kept apart from `config` since it may be set before the `restore` policy.
+    backup_dir: Option<PathBuf>,
+}
+
+impl ConfigurationBuilder
+{
+    pub fn master_db_path<P: Into<PathBuf>>(mut self, path: P) -> Self
+    {
+        self.config.master_db_path = Some(path.into());
+        self
+    }
+
+    pub fn lru_cache_cap(mut self, cap: usize) -> Self
+    {
+        self.config.lru_cache_cap = Some(cap);
+        self
+    }
+
+    pub fn max_open_connections(mut self, max: usize) -> Self
+    {
+        self.config.max_open_connections = Some(max);
+        self
+    }
+
+    pub fn cache_policy(mut self, policy: CachePolicy) -> Self
+    {
+        self.config.cache_policy = Some(policy);
+        self
+    }
+
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self
+    {
+        self.config.idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn stats_flush_interval(mut self, interval: Duration) -> Self
+    {
+        self.config.stats_flush_interval = Some(interval);
+        self
+    }
+
+    pub fn query_trace(mut self, options: QueryTraceOptions) -> Self
+    {
+        self.config.query_trace = Some(options);
+        self
+    }
+
+    pub fn missing_file_policy(mut self, policy: MissingFilePolicy) -> Self
+    {
+        self.config.missing_file_policy = Some(policy);
+        self
+    }
+
+    /// Sets a field from its key in a config file, see `Configuration` for the keys.
+    pub fn set(mut self, key: &str, value: &str) -> Result<Self, MultiTenantError>
+    {
+        match key {
+            "master_db_path" => self.config.master_db_path = Some(PathBuf::from(value)),
+            "lru_cache_cap" => self.config.lru_cache_cap = Some(parse(key, value)?),
+            "max_open_connections" => self.config.max_open_connections = Some(parse(key, value)?),
+            "cache_policy" => {
+                self.config.cache_policy = Some(match value.to_lowercase().as_str() {
+                    "lru" => CachePolicy::Lru,
+                    "lfu" => CachePolicy::Lfu,
+                    "ttl" => CachePolicy::Ttl(self.cache_ttl.unwrap_or_default()),
+                    _ => {
+                        return Err(MultiTenantError::InvalidConfiguration(format!(
+                            "unknown cache_policy `{}`, expected lru, lfu or ttl",
+                            value
+                        )))
+                    }
+                })
+            }
+            "cache_ttl_secs" => {
+                let cache_ttl = Duration::from_secs(parse(key, value)?);
+
+                if let Some(CachePolicy::Ttl(ttl)) = &mut self.config.cache_policy {
+                    *ttl = cache_ttl;
+                }
+
+                self.cache_ttl = Some(cache_ttl);
+            }
+            "idle_timeout_secs" => self.config.idle_timeout = Some(Duration::from_secs(parse(key, value)?)),
+            "stats_flush_interval_secs" => self.config.stats_flush_interval = Some(Duration::from_secs(parse(key, value)?)),
+            "slow_query_threshold_ms" => {
+                self.config
+                    .query_trace
+                    .get_or_insert_with(Default::default)
+                    .slow_query_threshold = Some(Duration::from_millis(parse(key, value)?))
+            }
+            "recent_queries" => {
+                self.config.query_trace.get_or_insert_with(Default::default).recent_queries = parse(key, value)?
+            }
+            "missing_file_policy" => {
+                self.config.missing_file_policy = Some(match value.to_lowercase().as_str() {
+                    "error" => MissingFilePolicy::Error,
+                    "recreate" => MissingFilePolicy::Recreate { init: None },
+                    "restore" => MissingFilePolicy::RestoreLatestBackup(self.backup_dir.clone().unwrap_or_default()),
+                    _ => {
+                        return Err(MultiTenantError::InvalidConfiguration(format!(
+                            "unknown missing_file_policy `{}`, expected error, recreate or restore",
+                            value
+                        )))
+                    }
+                })
+            }
+            "backup_dir" => {
+                let backup_dir = PathBuf::from(value);
+
+                if let Some(MissingFilePolicy::RestoreLatestBackup(dir)) = &mut self.config.missing_file_policy {
+                    *dir = backup_dir.clone();
+                }
+
+                self.backup_dir = Some(backup_dir);
+            }
+            _ => return Err(MultiTenantError::InvalidConfiguration(format!("unknown key `{}`", key))),
+        }
+
+        Ok(self)
+    }
+
+    /// Validates and returns the configuration.
+    pub fn build(self) -> Result<Configuration, MultiTenantError>
+    {
+        self.config.validate()?;
+
+        Ok(self.config)
+    }
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, MultiTenantError>
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|_| MultiTenantError::InvalidConfiguration(format!("`{}` is not a valid {}", value, key)))
 }
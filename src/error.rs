@@ -5,12 +5,26 @@ pub type SQLError = rusqlite::Error;
 pub type DynamicStdError = Box<dyn Error>;
 pub type SQLResult<T, E = SQLError> = Result<T, E>;
 
+/// Prefix of the `RAISE` message used by quota triggers, so their failures map to `QuotaExceeded`.
+pub(crate) const QUOTA_EXCEEDED_PREFIX: &str = "quota exceeded: ";
+
 #[derive(Debug, PartialEq)]
 pub enum MultiTenantError
 {
     TenantAlreadyExists(String),
     TenantNotFound(String),
     DatabaseError(String),
+    /// A tenant hit one of its quotas, such as its max size, connection or row limit.
+    QuotaExceeded(String),
+    /// Every open tenant connection is in use and `max_open_connections` has been reached, or every cached connection
+    /// is in use.
+    ConnectionLimitReached(String),
+    /// A `Configuration` failed to load or did not pass validation.
+    InvalidConfiguration(String),
+    /// A tenant id failed `TenantId` validation.
+    InvalidTenantId(String),
+    /// A registered tenant's database file does not exist, and the `MissingFilePolicy` could not bring it back.
+    TenantFileMissing(String),
 }
 
 impl Error for MultiTenantError {}
@@ -27,6 +41,11 @@ impl fmt::Display for MultiTenantError
                 write!(f, "Tenant '{}' not found", tenant_id)
             }
             MultiTenantError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+            MultiTenantError::QuotaExceeded(msg) => write!(f, "Quota exceeded: {}", msg),
+            MultiTenantError::ConnectionLimitReached(msg) => write!(f, "Connection limit reached: {}", msg),
+            MultiTenantError::InvalidConfiguration(msg) => write!(f, "Invalid configuration: {}", msg),
+            MultiTenantError::InvalidTenantId(msg) => write!(f, "Invalid tenant id: {}", msg),
+            MultiTenantError::TenantFileMissing(msg) => write!(f, "Tenant file missing: {}", msg),
         }
     }
 }
@@ -37,8 +56,15 @@ impl From<rusqlite::Error> for MultiTenantError
     {
         match err {
             rusqlite::Error::QueryReturnedNoRows => MultiTenantError::TenantNotFound("No row data found.".to_string()),
+            rusqlite::Error::SqliteFailure(err, _) if err.code == rusqlite::ErrorCode::DiskFull => {
+                MultiTenantError::QuotaExceeded("database is full, max page count or disk space reached".to_string())
+            }
             rusqlite::Error::SqliteFailure(_, msg) => {
                 if let Some(msg) = msg {
+                    if let Some(quota) = msg.strip_prefix(QUOTA_EXCEEDED_PREFIX) {
+                        return MultiTenantError::QuotaExceeded(quota.to_string());
+                    }
+
                     MultiTenantError::DatabaseError(msg.to_string())
                 } else {
                     MultiTenantError::DatabaseError("Failed to get database error message.".to_string())
@@ -0,0 +1,320 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use log::{debug, info, warn};
+use rusqlite::{Connection, OpenFlags};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+use crate::tenant::{open_existing_file, TenantConnection, TenantRecord};
+
+/// Selects which registered tenants a fan-out runs against.
+pub enum TenantFilter
+{
+    /// Every tenant in the master database.
+    All,
+    /// Only the listed tenant ids. Ids that are not registered are ignored.
+    Ids(Vec<String>),
+    /// Tenants whose id starts with the given prefix.
+    Prefix(String),
+    /// Tenants accepted by a custom predicate.
+    Matching(Box<dyn Fn(&TenantRecord) -> bool>),
+}
+
+impl TenantFilter
+{
+    pub(crate) fn matches(&self, tenant: &TenantRecord) -> bool
+    {
+        match self {
+            TenantFilter::All => true,
+            TenantFilter::Ids(ids) => ids.contains(&tenant.tenant_id),
+            TenantFilter::Prefix(prefix) => tenant.tenant_id.starts_with(prefix.as_str()),
+            TenantFilter::Matching(predicate) => predicate(tenant),
+        }
+    }
+}
+
+/// Reported to the progress callback each time a tenant finishes.
+pub struct FanOutProgress<'a>
+{
+    pub tenant_id: &'a str,
+    /// The amount of tenants finished so far, including this one.
+    pub completed: usize,
+    pub total: usize,
+}
+
+/// Options that control how `for_each_tenant_with` runs.
+#[derive(Default)]
+pub struct FanOutOptions<'a>
+{
+    /// The amount of worker threads used. `0` or `1` runs every tenant on the calling thread.
+    pub parallelism: usize,
+    /// When set to `true`, tenants that have not started yet are skipped.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called after every tenant, possibly from a worker thread.
+    pub progress: Option<&'a (dyn Fn(FanOutProgress) + Sync)>,
+}
+
+/// The per-tenant outcome of a fan-out, in registry order.
+#[derive(Debug)]
+pub struct FanOutReport<T>
+{
+    pub results: Vec<(String, T)>,
+    pub errors: Vec<(String, MultiTenantError)>,
+    /// Tenants that never ran because the fan-out was cancelled.
+    pub skipped: Vec<String>,
+}
+
+impl<T> FanOutReport<T>
+{
+    /// Returns `true` if every tenant ran without an error.
+    pub fn is_complete(&self) -> bool
+    {
+        self.errors.is_empty() && self.skipped.is_empty()
+    }
+}
+
+enum Outcome<T>
+{
+    Done(T),
+    Failed(MultiTenantError),
+    Skipped,
+}
+
+impl MultiTenantManager
+{
+    /// Runs `f` against every tenant selected by `filter`, collecting the result of each.
+    ///
+    /// See `for_each_tenant_with` for how connections are opened.
+    pub fn for_each_tenant<T, F>(
+        &mut self,
+        filter: TenantFilter,
+        parallelism: usize,
+        f: F,
+    ) -> SQLResult<FanOutReport<T>, MultiTenantError>
+    where
+        T: Send,
+        F: Fn(&str, &Connection) -> SQLResult<T, MultiTenantError> + Sync,
+    {
+        self.for_each_tenant_with(
+            filter,
+            FanOutOptions {
+                parallelism,
+                ..Default::default()
+            },
+            f,
+        )
+    }
+
+    /// Runs `f` against every tenant selected by `filter` with cancellation and progress reporting.
+    ///
+    /// Cached connections are used without touching their LRU position, and tenants that are not cached are opened
+    /// just for the call, so a fan-out never evicts hot tenants. With `parallelism > 1`, file backed tenants are
+    /// opened on their own connection inside a worker thread, while in-memory tenants still run on the calling
+    /// thread.
+    ///
+    /// An in-memory tenant that is not cached runs on a second connection to its shared-cache database. Shared-cache
+    /// connections lock whole tables, so `f` fails with `SQLITE_LOCKED` on tables another connection to the tenant,
+    /// such as one a caller still holds, has uncommitted writes on.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "tenant.for_each", skip_all))]
+    pub fn for_each_tenant_with<T, F>(
+        &mut self,
+        filter: TenantFilter,
+        options: FanOutOptions,
+        f: F,
+    ) -> SQLResult<FanOutReport<T>, MultiTenantError>
+    where
+        T: Send,
+        F: Fn(&str, &Connection) -> SQLResult<T, MultiTenantError> + Sync,
+    {
+        let tenants: Vec<TenantRecord> = self
+            .list_tenants()?
+            .into_iter()
+            .filter(|tenant| filter.matches(tenant))
+            .collect();
+
+        let total = tenants.len();
+        let completed = AtomicUsize::new(0);
+        let outcomes: Mutex<Vec<Option<Outcome<T>>>> = Mutex::new((0..total).map(|_| None).collect());
+
+        let is_cancelled = || options.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+
+        let finish = |index: usize, tenant_id: &str, outcome: Outcome<T>| {
+            if let Outcome::Failed(err) = &outcome {
+                warn!("Fan-out failed for ({}) tenant: {}", tenant_id, err);
+            }
+
+            if !matches!(outcome, Outcome::Skipped) {
+                let completed = completed.fetch_add(1, Ordering::Relaxed) + 1;
+
+                if let Some(progress) = options.progress {
+                    progress(FanOutProgress {
+                        tenant_id,
+                        completed,
+                        total,
+                    });
+                }
+            }
+
+            outcomes.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(outcome);
+        };
+
+        let (local, remote): (Vec<usize>, Vec<usize>) = if options.parallelism > 1 {
+            (0..total).partition(|&index| tenants[index].path.is_none())
+        } else {
+            ((0..total).collect(), Vec::new())
+        };
+
+        debug!("Fanning out over {} tenants ({} on worker threads).", total, remote.len());
+
+        let next = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..options.parallelism.min(remote.len()) {
+                scope.spawn(|| {
+                    while let Some(&index) = remote.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let tenant = &tenants[index];
+
+                        if is_cancelled() {
+                            finish(index, &tenant.tenant_id, Outcome::Skipped);
+                            continue;
+                        }
+
+                        let read_only = self.read_only_tenants.contains(&tenant.tenant_id);
+
+                        let outcome = match tenant
+                            .path
+                            .as_ref()
+                            .map(|path| Self::open_worker_connection(&tenant.tenant_id, path, read_only))
+                        {
+                            Some(Ok(connection)) => Self::run_tenant(&f, &tenant.tenant_id, &connection),
+                            Some(Err(err)) => Outcome::Failed(err),
+                            None => unreachable!("in-memory tenants are never sent to worker threads"),
+                        };
+
+                        finish(index, &tenant.tenant_id, outcome);
+                    }
+                });
+            }
+
+            for &index in &local {
+                let tenant = &tenants[index];
+
+                if is_cancelled() {
+                    finish(index, &tenant.tenant_id, Outcome::Skipped);
+                    continue;
+                }
+
+                let outcome = match self.peek_or_open(tenant) {
+                    Ok(tenant_connection) => Self::run_tenant(&f, &tenant.tenant_id, &tenant_connection.connection),
+                    Err(err) => Outcome::Failed(err),
+                };
+
+                finish(index, &tenant.tenant_id, outcome);
+            }
+        });
+
+        let mut report = FanOutReport {
+            results: Vec::new(),
+            errors: Vec::new(),
+            skipped: Vec::new(),
+        };
+
+        let outcomes = outcomes.into_inner().unwrap_or_else(|e| e.into_inner());
+
+        for (tenant, outcome) in tenants.into_iter().zip(outcomes) {
+            match outcome.unwrap_or(Outcome::Skipped) {
+                Outcome::Done(value) => report.results.push((tenant.tenant_id, value)),
+                Outcome::Failed(err) => report.errors.push((tenant.tenant_id, err)),
+                Outcome::Skipped => report.skipped.push(tenant.tenant_id),
+            }
+        }
+
+        info!(
+            "Fan-out finished: {} succeeded, {} failed, {} skipped.",
+            report.results.len(),
+            report.errors.len(),
+            report.skipped.len()
+        );
+
+        Ok(report)
+    }
+
+    fn run_tenant<T, F>(f: &F, tenant_id: &str, connection: &Connection) -> Outcome<T>
+    where
+        F: Fn(&str, &Connection) -> SQLResult<T, MultiTenantError>,
+    {
+        match f(tenant_id, connection) {
+            Ok(value) => Outcome::Done(value),
+            Err(err) => Outcome::Failed(err),
+        }
+    }
+
+    /// Opens a connection for a worker thread, read-only for tenants with the `ReadOnly` access mode.
+    ///
+    /// Fan-outs do not apply the `MissingFilePolicy`, a tenant whose file is missing fails with `TenantFileMissing`.
+    fn open_worker_connection(tenant_id: &str, path: &Path, read_only: bool) -> SQLResult<Connection, MultiTenantError>
+    {
+        Self::check_file_exists(tenant_id, path)?;
+
+        if read_only {
+            Ok(Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?)
+        } else {
+            Ok(open_existing_file(path)?)
+        }
+    }
+
+    fn check_file_exists(tenant_id: &str, path: &Path) -> SQLResult<(), MultiTenantError>
+    {
+        if path.exists() {
+            Ok(())
+        } else {
+            Err(MultiTenantError::TenantFileMissing(format!(
+                "'{}' at {}",
+                tenant_id,
+                path.display()
+            )))
+        }
+    }
+
+    /// Returns the cached connection without promoting it, or opens a connection that is not added to the cache.
+    ///
+    /// Tenants with the `ReadOnly` access mode get a read-only connection. Like worker threads, it does not apply the
+    /// `MissingFilePolicy`.
+    ///
+    /// An in-memory tenant that is not cached gets a second connection to the shared cache its anchor keeps alive, and
+    /// fails if this manager has not opened it yet. Shared-cache connections lock whole tables, so `f` fails with
+    /// `SQLITE_LOCKED` if it touches a table another connection to the tenant is writing to.
+    fn peek_or_open(&self, tenant: &TenantRecord) -> SQLResult<TenantConnection, MultiTenantError>
+    {
+        let tenant_id = tenant.tenant_id.as_str();
+
+        if !self.read_only_tenants.contains(tenant_id) {
+            return match (self.cache.peek(tenant_id), &tenant.path) {
+                (Some(connection), _) => Ok(connection.clone()),
+                (None, Some(path)) => {
+                    Self::check_file_exists(tenant_id, path)?;
+                    Ok(TenantConnection::open_existing(path)?)
+                }
+                (None, None) => self.open_anchored_memory_tenant(tenant_id),
+            };
+        }
+
+        match (self.readonly_cache.peek(tenant_id), &tenant.path) {
+            (Some(connection), _) => Ok(connection.clone()),
+            (None, Some(path)) => {
+                Self::check_file_exists(tenant_id, path)?;
+                Ok(TenantConnection::open_read_only(path)?)
+            }
+            (None, None) => Err(MultiTenantError::DatabaseError(format!(
+                "Tenant '{}' is in-memory and can not be opened read-only",
+                tenant_id
+            ))),
+        }
+    }
+}
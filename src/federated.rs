@@ -0,0 +1,172 @@
+use std::path::Path;
+
+use log::debug;
+use rusqlite::limits::Limit;
+use rusqlite::{params, Connection, OpenFlags, Row};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+use crate::statements::{quote_identifier, quote_literal};
+
+/// A tenant database attached to the scratch connection of a federated query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttachedTenant
+{
+    /// The schema name the tenant is attached under, e.g. `tenant_0`.
+    pub alias: String,
+    pub tenant_id: String,
+}
+
+/// Builds a `UNION ALL` select over `table` in every attached tenant.
+///
+/// Each row is prefixed with a `tenant_id` column holding the tenant it came from.
+pub fn union_all_sql(table: &str, attached: &[AttachedTenant]) -> String
+{
+    attached
+        .iter()
+        .map(|tenant| {
+            format!(
+                "SELECT {} AS tenant_id, * FROM {}.{}",
+                quote_literal(&tenant.tenant_id),
+                quote_identifier(&tenant.alias),
+                quote_identifier(table)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ")
+}
+
+/// Builds a `CREATE TEMP VIEW` named after `table` that unions it across every attached tenant.
+pub fn union_view_sql(table: &str, attached: &[AttachedTenant]) -> String
+{
+    format!(
+        "CREATE TEMP VIEW {} AS {};",
+        quote_identifier(table),
+        union_all_sql(table, attached)
+    )
+}
+
+/// Builds a read-only `file:` URI, escaping the characters that would otherwise end the path.
+fn read_only_uri(path: &Path) -> String
+{
+    let mut uri = String::from("file:");
+
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '%' => uri.push_str("%25"),
+            '?' => uri.push_str("%3f"),
+            '#' => uri.push_str("%23"),
+            _ => uri.push(c),
+        }
+    }
+
+    uri.push_str("?mode=ro");
+    uri
+}
+
+impl MultiTenantManager
+{
+    /// Runs `sql` across several tenants at once by attaching their files to a scratch connection.
+    ///
+    /// Every table in `union_tables` is exposed to `sql` as a temp view of the same name that unions the table
+    /// across the attached tenants (see `union_view_sql`). Tenants are attached read-only in batches that fit under
+    /// SQLite's attach limit, and `sql` runs once per batch, so aggregates are per batch rather than global.
+    ///
+    /// In-memory tenants can not be attached and return a `DatabaseError`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "tenant.federated_query", skip_all, fields(tenants = tenant_ids.len()))
+    )]
+    pub fn federated_query<T, F>(
+        &mut self,
+        tenant_ids: &[&str],
+        union_tables: &[&str],
+        sql: &str,
+        mut f: F,
+    ) -> SQLResult<Vec<T>, MultiTenantError>
+    where
+        F: FnMut(&Row) -> SQLResult<T>,
+    {
+        let mut paths = Vec::with_capacity(tenant_ids.len());
+
+        for tenant_id in tenant_ids {
+            let tenant = self
+                .get_tenant(tenant_id)?
+                .ok_or_else(|| MultiTenantError::TenantNotFound(tenant_id.to_string()))?;
+
+            match tenant.path {
+                Some(path) => paths.push((tenant.tenant_id, path)),
+                None => {
+                    return Err(MultiTenantError::DatabaseError(format!(
+                        "Tenant '{}' is in-memory and can not be attached",
+                        tenant_id
+                    )))
+                }
+            }
+        }
+
+        let scratch = Connection::open_in_memory_with_flags(OpenFlags::default() | OpenFlags::SQLITE_OPEN_URI)?;
+        let batch_size = scratch.limit(Limit::SQLITE_LIMIT_ATTACHED).max(1) as usize;
+
+        let mut rows = Vec::new();
+
+        for batch in paths.chunks(batch_size) {
+            let attached: Vec<AttachedTenant> = batch
+                .iter()
+                .enumerate()
+                .map(|(index, (tenant_id, _))| AttachedTenant {
+                    alias: format!("tenant_{}", index),
+                    tenant_id: tenant_id.clone(),
+                })
+                .collect();
+
+            for (tenant, (_, path)) in attached.iter().zip(batch) {
+                scratch.execute(
+                    &format!("ATTACH DATABASE ?1 AS {};", quote_identifier(&tenant.alias)),
+                    params![read_only_uri(path)],
+                )?;
+            }
+
+            let result = Self::run_federated_batch(&scratch, &attached, union_tables, sql, &mut f, &mut rows);
+
+            for table in union_tables {
+                scratch.execute(&format!("DROP VIEW IF EXISTS temp.{};", quote_identifier(table)), [])?;
+            }
+
+            for tenant in &attached {
+                scratch.execute(&format!("DETACH DATABASE {};", quote_identifier(&tenant.alias)), [])?;
+            }
+
+            result?;
+
+            debug!("Federated query ran across {} attached tenants.", attached.len());
+        }
+
+        Ok(rows)
+    }
+
+    fn run_federated_batch<T, F>(
+        scratch: &Connection,
+        attached: &[AttachedTenant],
+        union_tables: &[&str],
+        sql: &str,
+        f: &mut F,
+        rows: &mut Vec<T>,
+    ) -> SQLResult<(), MultiTenantError>
+    where
+        F: FnMut(&Row) -> SQLResult<T>,
+    {
+        for table in union_tables {
+            scratch.execute(&union_view_sql(table, attached), [])?;
+        }
+
+        let mut statement = scratch.prepare(sql)?;
+        let mut query = statement.query([])?;
+
+        while let Some(row) = query.next()? {
+            rows.push(f(row)?);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use rusqlite::{Connection, ErrorCode, OpenFlags, TransactionBehavior};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+use crate::tenant::TenantRecord;
+
+/// Files SQLite keeps next to a database, which are not orphans as long as the database is registered.
+pub(crate) const SIDECAR_SUFFIXES: [&str; 3] = ["-wal", "-shm", "-journal"];
+
+/// The database file at `path` followed by the journal files SQLite may keep next to it, whether they exist or not.
+pub fn database_files<P: AsRef<Path>>(path: P) -> Vec<PathBuf>
+{
+    let path = path.as_ref();
+    let mut files = vec![path.to_path_buf()];
+
+    files.extend(SIDECAR_SUFFIXES.iter().map(|suffix| {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        PathBuf::from(file)
+    }));
+
+    files
+}
+
+/// The integrity check `health_check` runs on tenant databases.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum IntegrityCheck
+{
+    /// Only check that tenant files exist and can be read.
+    #[default]
+    None,
+    /// `PRAGMA quick_check`, which skips verifying that indexes match their tables.
+    Quick,
+    /// `PRAGMA integrity_check`, which reads every page of the database.
+    Full,
+}
+
+impl IntegrityCheck
+{
+    fn pragma(&self) -> Option<&'static str>
+    {
+        match self {
+            IntegrityCheck::None => None,
+            IntegrityCheck::Quick => Some("PRAGMA quick_check;"),
+            IntegrityCheck::Full => Some("PRAGMA integrity_check;"),
+        }
+    }
+}
+
+/// Options that control what `health_check` verifies.
+#[derive(Debug, Clone, Default)]
+pub struct HealthCheckOptions
+{
+    pub integrity_check: IntegrityCheck,
+    /// Runs the integrity check on at most this many tenants per call, picking up where the last call stopped so
+    /// every tenant is covered over time. If `None` is provided, every tenant is checked.
+    pub sample: Option<usize>,
+    /// The directory tenant files are stored in. If set, files in it that are not a registered tenant database, its
+    /// journal, or the master database are reported as orphaned.
+    pub storage_dir: Option<PathBuf>,
+}
+
+/// The result of a `health_check`.
+#[derive(Debug, Default)]
+pub struct HealthReport
+{
+    /// Why the master database could not be written to, `None` if it is writable.
+    pub master_error: Option<String>,
+    /// Registered tenants with a file that does not exist.
+    pub missing: Vec<String>,
+    /// Registered tenants with a file that exists but could not be read, with the error.
+    pub unreadable: Vec<(String, String)>,
+    /// Tenants that are not valid databases or failed the integrity check, with what SQLite reported.
+    pub corrupted: Vec<(String, Vec<String>)>,
+    /// Files in `HealthCheckOptions::storage_dir` without a tenant in the master database.
+    pub orphaned: Vec<PathBuf>,
+    /// The tenants the integrity check ran on.
+    pub integrity_checked: Vec<String>,
+}
+
+impl HealthReport
+{
+    /// Returns `true` if the master is writable and every tenant is readable and intact.
+    ///
+    /// Orphaned files do not make the manager unhealthy, they only take up space.
+    pub fn is_healthy(&self) -> bool
+    {
+        self.master_error.is_none() && self.missing.is_empty() && self.unreadable.is_empty() && self.corrupted.is_empty()
+    }
+}
+
+impl MultiTenantManager
+{
+    /// Verifies the master database is writable and every file backed tenant exists and can be read.
+    ///
+    /// Tenant files are opened on their own read-only connection, so cached connections and the cache order are
+    /// left alone. In-memory tenants have no file and are skipped.
+    pub fn health_check(&mut self, options: HealthCheckOptions) -> SQLResult<HealthReport, MultiTenantError>
+    {
+        let mut report = HealthReport {
+            master_error: self.check_master_writable().err().map(|err| err.to_string()),
+            ..Default::default()
+        };
+
+        let tenants: Vec<TenantRecord> = self
+            .list_tenants()?
+            .into_iter()
+            .filter(|tenant| tenant.path.is_some())
+            .collect();
+
+        let integrity_checked = self.integrity_sample(&tenants, options.sample);
+
+        for tenant in &tenants {
+            let Some(path) = &tenant.path else {
+                continue;
+            };
+
+            if !path.is_file() {
+                report.missing.push(tenant.tenant_id.clone());
+                continue;
+            }
+
+            let check = options
+                .integrity_check
+                .pragma()
+                .filter(|_| integrity_checked.contains(tenant.tenant_id.as_str()));
+
+            if check.is_some() {
+                report.integrity_checked.push(tenant.tenant_id.clone());
+            }
+
+            match Self::check_tenant_file(path, check) {
+                Ok(problems) if problems.is_empty() => {}
+                Ok(problems) => report.corrupted.push((tenant.tenant_id.clone(), problems)),
+                Err(err)
+                    if matches!(
+                        err.sqlite_error_code(),
+                        Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase)
+                    ) =>
+                {
+                    report.corrupted.push((tenant.tenant_id.clone(), vec![err.to_string()]))
+                }
+                Err(err) => report.unreadable.push((tenant.tenant_id.clone(), err.to_string())),
+            }
+        }
+
+        if let Some(storage_dir) = &options.storage_dir {
+            report.orphaned = self.orphaned_files(storage_dir, &tenants)?;
+        }
+
+        if report.is_healthy() {
+            info!("Health check passed for {} tenants.", tenants.len());
+        } else {
+            warn!(
+                "Health check failed: master error {:?}, {} missing, {} unreadable, {} corrupted tenants.",
+                report.master_error,
+                report.missing.len(),
+                report.unreadable.len(),
+                report.corrupted.len()
+            );
+        }
+
+        Ok(report)
+    }
+
+    /// Takes the write lock on the master and writes to it, rolling the write back.
+    fn check_master_writable(&mut self) -> SQLResult<()>
+    {
+        let tx = self.master_db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute_batch("CREATE TABLE health_check_probe (id INTEGER); DROP TABLE health_check_probe;")?;
+        tx.rollback()
+    }
+
+    /// Picks the tenants the integrity check runs on, moving the sample window forward for the next call.
+    fn integrity_sample<'a>(&mut self, tenants: &'a [TenantRecord], sample: Option<usize>) -> HashSet<&'a str>
+    {
+        let Some(sample) = sample.filter(|sample| *sample < tenants.len()) else {
+            return tenants.iter().map(|tenant| tenant.tenant_id.as_str()).collect();
+        };
+
+        let start = self.integrity_cursor % tenants.len();
+        self.integrity_cursor = start + sample;
+
+        tenants
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(sample)
+            .map(|tenant| tenant.tenant_id.as_str())
+            .collect()
+    }
+
+    /// Opens a tenant file read-only and runs `check` on it. Returns the problems it reported.
+    fn check_tenant_file(path: &Path, check: Option<&str>) -> SQLResult<Vec<String>>
+    {
+        let connection =
+            Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+
+        // Reading the schema fails on files that are not databases, since opening is lazy.
+        connection.query_row("SELECT COUNT(*) FROM sqlite_master;", [], |_| Ok(()))?;
+
+        let Some(check) = check else {
+            return Ok(Vec::new());
+        };
+
+        let mut statement = connection.prepare(check)?;
+        let messages = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<SQLResult<Vec<_>>>()?;
+
+        Ok(messages.into_iter().filter(|message| message != "ok").collect())
+    }
+
+    /// Lists the files in `storage_dir` that are not a registered tenant database, its journal, or the master.
+    pub(crate) fn orphaned_files(
+        &self,
+        storage_dir: &Path,
+        tenants: &[TenantRecord],
+    ) -> SQLResult<Vec<PathBuf>, MultiTenantError>
+    {
+        let mut known: HashSet<PathBuf> = tenants
+            .iter()
+            .filter_map(|tenant| tenant.path.as_deref())
+            .filter_map(|path| path.canonicalize().ok())
+            .collect();
+
+        if let Some(master) = self.master_db.path().filter(|path| !path.is_empty()) {
+            known.extend(Path::new(master).canonicalize());
+        }
+
+        let entries = fs::read_dir(storage_dir).map_err(|err| {
+            MultiTenantError::DatabaseError(format!("Failed to read storage dir {}: {}", storage_dir.display(), err))
+        })?;
+
+        let mut orphaned = Vec::new();
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if !path.is_file() {
+                continue;
+            }
+
+            let database = path
+                .to_str()
+                .and_then(|name| SIDECAR_SUFFIXES.iter().find_map(|suffix| name.strip_suffix(suffix)))
+                .map(PathBuf::from)
+                .unwrap_or_else(|| path.clone());
+
+            if !database.canonicalize().is_ok_and(|database| known.contains(&database)) {
+                orphaned.push(path);
+            }
+        }
+
+        orphaned.sort();
+
+        Ok(orphaned)
+    }
+}
@@ -0,0 +1,413 @@
+//! A small JSON admin API over HTTP, behind the `http-admin` feature.
+//!
+//! | method | path | |
+//! | --- | --- | --- |
+//! | `GET` | `/tenants` | lists every tenant |
+//! | `POST` | `/tenants` | adds a tenant from `{"tenant_id": "...", "path": "..."}`, `path` is optional, see below |
+//! | `GET` | `/tenants/{id}` | the tenant with its size and access stats |
+//! | `DELETE` | `/tenants/{id}` | removes a tenant, leaving its file in place |
+//! | `POST` | `/tenants/{id}/backup` | copies the tenant into `HttpAdminOptions::backup_dir` |
+//! | `GET` | `/health` | `health_check` without an integrity check, `503` if unhealthy |
+//! | `GET` | `/metrics` | the cache stats and tenant count |
+//!
+//! The `path` of a new tenant is a file name inside `HttpAdminOptions::storage_dir`, without it the tenant is
+//! in-memory. Every request needs an `Authorization: Bearer {token}` header.
+
+use std::fs;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use log::{info, warn};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::health::HealthCheckOptions;
+use crate::manager::MultiTenantManager;
+use crate::tenant::TenantRecord;
+
+/// Request bodies larger than this are rejected.
+const MAX_BODY_BYTES: u64 = 64 * 1024;
+
+/// Where the admin server listens and how it authenticates requests.
+#[derive(Debug, Clone)]
+pub struct HttpAdminOptions
+{
+    /// Defaults to `127.0.0.1:7878`, so the API is only reachable from the same host.
+    pub addr: SocketAddr,
+    /// The bearer token every request must send. Can not be empty.
+    pub token: String,
+    /// The directory `POST /tenants/{id}/backup` writes backups to. If `None` is provided, backups are disabled.
+    pub backup_dir: Option<PathBuf>,
+    /// The directory `POST /tenants` creates tenant files in, clients only pick the file name. If `None` is provided,
+    /// only in-memory tenants can be added.
+    pub storage_dir: Option<PathBuf>,
+}
+
+impl Default for HttpAdminOptions
+{
+    fn default() -> Self
+    {
+        Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], 7878)),
+            token: String::new(),
+            backup_dir: None,
+            storage_dir: None,
+        }
+    }
+}
+
+/// An HTTP server exposing the admin API of a `MultiTenantManager`.
+///
+/// The manager is not shared with the server, it is passed to `poll` or `serve`, which handle requests on the
+/// calling thread.
+pub struct HttpAdminServer
+{
+    server: Server,
+    token: String,
+    backup_dir: Option<PathBuf>,
+    storage_dir: Option<PathBuf>,
+}
+
+/// An error response, with its status code.
+struct ApiError(u16, String);
+
+impl From<MultiTenantError> for ApiError
+{
+    fn from(err: MultiTenantError) -> Self
+    {
+        let status = match &err {
+            MultiTenantError::TenantNotFound(_) => 404,
+            MultiTenantError::TenantAlreadyExists(_) => 409,
+            MultiTenantError::QuotaExceeded(_) | MultiTenantError::ConnectionLimitReached(_) => 429,
+            MultiTenantError::InvalidConfiguration(_) | MultiTenantError::InvalidTenantId(_) => 400,
+            MultiTenantError::DatabaseError(_) | MultiTenantError::TenantFileMissing(_) => 500,
+        };
+
+        ApiError(status, err.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for ApiError
+{
+    fn from(err: rusqlite::Error) -> Self
+    {
+        MultiTenantError::from(err).into()
+    }
+}
+
+type ApiResult = Result<(u16, Value), ApiError>;
+
+impl HttpAdminServer
+{
+    /// Starts listening on `options.addr`. Requests are only handled while `poll` or `serve` runs.
+    pub fn bind(options: HttpAdminOptions) -> SQLResult<Self, MultiTenantError>
+    {
+        if options.token.is_empty() {
+            return Err(MultiTenantError::InvalidConfiguration(
+                "the http admin token can not be empty".to_string(),
+            ));
+        }
+
+        let server = Server::http(options.addr).map_err(|err| {
+            MultiTenantError::InvalidConfiguration(format!("failed to bind http admin to {}: {}", options.addr, err))
+        })?;
+
+        let server = Self {
+            server,
+            token: options.token,
+            backup_dir: options.backup_dir,
+            storage_dir: options.storage_dir,
+        };
+
+        info!("HTTP admin listening on {}.", server.local_addr());
+
+        Ok(server)
+    }
+
+    /// The address the server is bound to, which has the actual port when binding to port `0`.
+    pub fn local_addr(&self) -> SocketAddr
+    {
+        self.server
+            .server_addr()
+            .to_ip()
+            .expect("the http admin only binds to ip addresses")
+    }
+
+    /// Handles the requests that arrive within `timeout`, returning after the first one. Returns how many were
+    /// handled, so it can run next to `MultiTenantManager::tick` in an application's own loop.
+    pub fn poll(&self, manager: &mut MultiTenantManager, timeout: Duration) -> usize
+    {
+        let mut handled = 0;
+        let mut wait = timeout;
+
+        loop {
+            match self.server.recv_timeout(wait) {
+                Ok(Some(request)) => {
+                    self.handle(manager, request);
+                    handled += 1;
+                    // Drain whatever else is queued without waiting again.
+                    wait = Duration::ZERO;
+                }
+                Ok(None) => return handled,
+                Err(err) => {
+                    warn!("HTTP admin failed to receive a request: {}", err);
+                    return handled;
+                }
+            }
+        }
+    }
+
+    /// Handles requests until the server fails to accept one.
+    pub fn serve(&self, manager: &mut MultiTenantManager)
+    {
+        loop {
+            match self.server.recv() {
+                Ok(request) => self.handle(manager, request),
+                Err(err) => {
+                    warn!("HTTP admin stopped: {}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle(&self, manager: &mut MultiTenantManager, mut request: Request)
+    {
+        let (status, body) = match self.route(manager, &mut request) {
+            Ok(response) => response,
+            Err(ApiError(status, message)) => (status, json!({ "error": message })),
+        };
+
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("valid header"));
+
+        let url = request.url().to_string();
+
+        if let Err(err) = request.respond(response) {
+            warn!("HTTP admin failed to respond to {}: {}", url, err);
+        }
+    }
+
+    fn route(&self, manager: &mut MultiTenantManager, request: &mut Request) -> ApiResult
+    {
+        if !self.is_authorized(request) {
+            warn!("HTTP admin rejected an unauthorized {} {}.", request.method(), request.url());
+            return Err(ApiError(401, "missing or invalid bearer token".to_string()));
+        }
+
+        let path = request.url().split('?').next().unwrap_or_default().to_string();
+        let segments: Vec<String> = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(percent_decode)
+            .collect();
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+        match (request.method(), segments.as_slice()) {
+            (Method::Get, ["tenants"]) => list_tenants(manager),
+            (Method::Post, ["tenants"]) => {
+                let body = read_json(request)?;
+                self.add_tenant(manager, body)
+            }
+            (Method::Get, ["tenants", tenant_id]) => tenant_info(manager, tenant_id),
+            (Method::Delete, ["tenants", tenant_id]) => {
+                manager.remove_tenant(tenant_id)?;
+                Ok((200, json!({ "removed": tenant_id })))
+            }
+            (Method::Post, ["tenants", tenant_id, "backup"]) => self.backup_tenant(manager, tenant_id),
+            (Method::Get, ["health"]) => health(manager),
+            (Method::Get, ["metrics"]) => metrics(manager),
+            _ => Err(ApiError(404, format!("no route for {} {}", request.method(), path))),
+        }
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool
+    {
+        request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Authorization"))
+            .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.trim().as_bytes(), self.token.as_bytes()))
+    }
+
+    fn add_tenant(&self, manager: &mut MultiTenantManager, body: Value) -> ApiResult
+    {
+        let Some(tenant_id) = body["tenant_id"].as_str() else {
+            return Err(ApiError(400, "`tenant_id` is required".to_string()));
+        };
+
+        let path = match &body["path"] {
+            Value::Null => None,
+            Value::String(path) => Some(self.storage_path(path)?),
+            _ => return Err(ApiError(400, "`path` must be a string".to_string())),
+        };
+
+        if manager.get_tenant(tenant_id)?.is_some() {
+            return Err(MultiTenantError::TenantAlreadyExists(tenant_id.to_string()).into());
+        }
+
+        manager.add_tenant(tenant_id, path.clone())?;
+
+        Ok((201, json!({ "tenant_id": tenant_id, "path": path })))
+    }
+
+    /// Resolves the `path` a client sent to a file directly inside `storage_dir`, so it can not point anywhere else.
+    fn storage_path(&self, path: &str) -> Result<PathBuf, ApiError>
+    {
+        let Some(storage_dir) = &self.storage_dir else {
+            return Err(ApiError(400, "file backed tenants are not enabled".to_string()));
+        };
+
+        let mut components = Path::new(path).components();
+
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) => Ok(storage_dir.join(name)),
+            _ => Err(ApiError(400, format!("`path` must be a file name, not {:?}", path))),
+        }
+    }
+
+    fn backup_tenant(&self, manager: &mut MultiTenantManager, tenant_id: &str) -> ApiResult
+    {
+        let Some(backup_dir) = &self.backup_dir else {
+            return Err(ApiError(400, "backups are not enabled".to_string()));
+        };
+
+        let destination = manager.backup_tenant(tenant_id, backup_dir)?;
+
+        info!("HTTP admin backed up ({}) tenant to {}.", tenant_id, destination.display());
+
+        Ok((201, json!({ "tenant_id": tenant_id, "backup": destination })))
+    }
+}
+
+fn list_tenants(manager: &MultiTenantManager) -> ApiResult
+{
+    let tenants = manager.list_tenants()?;
+
+    Ok((200, json!(tenants.iter().map(tenant_json).collect::<Vec<_>>())))
+}
+
+fn tenant_info(manager: &mut MultiTenantManager, tenant_id: &str) -> ApiResult
+{
+    let tenant = manager
+        .get_tenant(tenant_id)?
+        .ok_or_else(|| MultiTenantError::TenantNotFound(tenant_id.to_string()))?;
+    let stats = manager.tenant_stats(tenant_id)?;
+    let size = tenant
+        .path
+        .as_ref()
+        .and_then(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len());
+
+    Ok((
+        200,
+        json!({
+            "tenant": tenant_json(&tenant),
+            "size_bytes": size,
+            "last_accessed_at": stats.as_ref().and_then(|stats| stats.last_accessed_at.clone()),
+            "access_count": stats.as_ref().map(|stats| stats.access_count),
+            "open_count": stats.as_ref().map(|stats| stats.open_count),
+        }),
+    ))
+}
+
+fn health(manager: &mut MultiTenantManager) -> ApiResult
+{
+    let report = manager.health_check(HealthCheckOptions::default())?;
+    let healthy = report.is_healthy();
+
+    Ok((
+        if healthy { 200 } else { 503 },
+        json!({
+            "healthy": healthy,
+            "master_error": report.master_error,
+            "missing": report.missing,
+            "unreadable": report.unreadable,
+            "corrupted": report.corrupted,
+        }),
+    ))
+}
+
+fn metrics(manager: &mut MultiTenantManager) -> ApiResult
+{
+    let stats = manager.cache_stats();
+
+    Ok((
+        200,
+        json!({
+            "tenants": manager.tenant_count(),
+            "cache_hits": stats.hits,
+            "cache_misses": stats.misses,
+            "cache_evictions": stats.evictions,
+            "cached_connections": stats.cached,
+            "detached_connections": stats.detached,
+            "open_connections": stats.open_connections,
+            "max_open_connections": stats.max_open_connections,
+        }),
+    ))
+}
+
+fn tenant_json(tenant: &TenantRecord) -> Value
+{
+    json!({
+        "tenant_id": tenant.tenant_id,
+        "path": tenant.path,
+        "created_at": tenant.created_at,
+    })
+}
+
+fn read_json(request: &mut Request) -> Result<Value, ApiError>
+{
+    let mut body = String::new();
+
+    request
+        .as_reader()
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_string(&mut body)
+        .map_err(|err| ApiError(400, format!("failed to read the request body: {}", err)))?;
+
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return Err(ApiError(413, "the request body is too large".to_string()));
+    }
+
+    serde_json::from_str(&body).map_err(|err| ApiError(400, format!("invalid json body: {}", err)))
+}
+
+/// Compares the token without returning early, so response times do not leak how much of it matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool
+{
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Decodes `%XX` escapes in a path segment. Valid tenant ids never need escaping, but clients may escape them anyway.
+fn percent_decode(segment: &str) -> String
+{
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| segment.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -1,8 +1,30 @@
+mod access;
+mod cache;
 mod config;
 mod error;
+mod fanout;
+mod federated;
+mod health;
+#[cfg(feature = "http-admin")]
+mod http_admin;
 mod logger;
+mod maintenance;
 mod manager;
+mod memory;
+mod observer;
 pub mod prelude;
+mod query_trace;
+mod quota;
+mod reaper;
+mod reconcile;
+mod recovery;
+mod shutdown;
 mod statements;
+mod stats;
+mod telemetry;
 mod tenant;
+mod tenant_id;
 mod test;
+mod typed_manager;
+mod usage;
+mod warmup;
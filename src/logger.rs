@@ -1,8 +1,13 @@
-use flexi_logger::Duplicate;
+#[cfg(feature = "logger")]
+use std::path::PathBuf;
 
-#[derive(Clone)]
+#[cfg(feature = "logger")]
+use flexi_logger::{Duplicate, FileSpec, FlexiLoggerError, Logger, LoggerHandle};
+
+#[derive(Clone, Default)]
 pub enum LogLevel
 {
+    #[default]
     Info,
     Warn,
     Error,
@@ -25,6 +30,7 @@ impl LogLevel
         }
     }
 
+    #[cfg(feature = "logger")]
     pub fn as_dup(&self) -> Duplicate
     {
         match self {
@@ -38,10 +44,17 @@ impl LogLevel
     }
 }
 
-impl Default for LogLevel
+/// Starts a global flexi_logger writing to files in `log_dir` and duplicating to stdout.
+///
+/// The manager only emits `log` records and never installs a logger itself, so this is for applications that do not
+/// have one. If `None` is passed as `log_dir`, it will default to 'logs' in your project root. Logging stops once the
+/// returned handle is dropped.
+#[cfg(feature = "logger")]
+pub fn init_logger(log_level: LogLevel, log_dir: Option<PathBuf>) -> Result<LoggerHandle, FlexiLoggerError>
 {
-    fn default() -> Self
-    {
-        LogLevel::Info
-    }
+    Logger::try_with_str(log_level.as_str())?
+        .log_to_file(FileSpec::default().directory(log_dir.unwrap_or(PathBuf::from("logs"))))
+        .duplicate_to_stdout(log_level.as_dup())
+        .format(flexi_logger::detailed_format)
+        .start()
 }
@@ -0,0 +1,255 @@
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{debug, info, warn};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::fanout::TenantFilter;
+use crate::manager::MultiTenantManager;
+use crate::statements::SqlStatement;
+use crate::stats::unix_millis;
+use crate::tenant::{open_existing_file, TenantRecord};
+
+/// A maintenance operation run on a tenant database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaintenanceTask
+{
+    /// `VACUUM`, which rebuilds the whole file. Needs as much free disk space as the database takes.
+    Vacuum,
+    /// `PRAGMA incremental_vacuum`, freeing up to the given amount of pages, or every free page if `None`. Does
+    /// nothing unless the tenant was created with `auto_vacuum = INCREMENTAL`.
+    IncrementalVacuum(Option<u32>),
+    /// `ANALYZE`, which gathers statistics for the query planner on every index.
+    Analyze,
+    /// `PRAGMA optimize`, which only analyzes the tables that need it.
+    Optimize,
+}
+
+impl MaintenanceTask
+{
+    fn sql(&self) -> String
+    {
+        match self {
+            MaintenanceTask::Vacuum => "VACUUM;".to_string(),
+            MaintenanceTask::IncrementalVacuum(Some(pages)) => format!("PRAGMA incremental_vacuum({});", pages),
+            MaintenanceTask::IncrementalVacuum(None) => "PRAGMA incremental_vacuum;".to_string(),
+            MaintenanceTask::Analyze => "ANALYZE;".to_string(),
+            MaintenanceTask::Optimize => "PRAGMA optimize;".to_string(),
+        }
+    }
+}
+
+/// Options that control which tenants `run_maintenance` picks and how fast it goes.
+#[derive(Debug, Clone)]
+pub struct MaintenanceOptions
+{
+    /// The tasks run on every picked tenant, in order.
+    pub tasks: Vec<MaintenanceTask>,
+    /// Only picks tenants whose free pages make up more than this share of their pages, from `0.0` to `1.0`.
+    /// If `None` is provided, tenants are picked whatever their freelist.
+    pub min_freelist_ratio: Option<f64>,
+    /// Skips tenants maintained more recently than this. If `None` is provided, tenants are picked however
+    /// recently they were maintained.
+    pub min_interval: Option<Duration>,
+    /// Maintains at most this many tenants per run, the least recently maintained first. Tenants past the limit
+    /// are reported as deferred.
+    pub max_tenants: Option<usize>,
+    /// Caps how many database pages are maintained per second, sleeping between tenants to bound I/O.
+    pub max_pages_per_second: Option<u64>,
+}
+
+impl Default for MaintenanceOptions
+{
+    /// `PRAGMA optimize` on every tenant, without rate limiting.
+    fn default() -> Self
+    {
+        Self {
+            tasks: vec![MaintenanceTask::Optimize],
+            min_freelist_ratio: None,
+            min_interval: None,
+            max_tenants: None,
+            max_pages_per_second: None,
+        }
+    }
+}
+
+/// The size of a tenant before and after its maintenance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaintenanceStats
+{
+    pub page_count_before: u64,
+    pub page_count_after: u64,
+    pub freelist_count_before: u64,
+    pub freelist_count_after: u64,
+}
+
+/// What `run_maintenance` did, in the order tenants were picked.
+#[derive(Debug, Default)]
+pub struct MaintenanceReport
+{
+    pub maintained: Vec<(String, MaintenanceStats)>,
+    pub failed: Vec<(String, MultiTenantError)>,
+    /// Tenants left out by `min_freelist_ratio`, or because they are read-only.
+    pub skipped: Vec<String>,
+    /// Tenants left for a later run by `max_tenants`.
+    pub deferred: Vec<String>,
+}
+
+impl MultiTenantManager
+{
+    /// Runs maintenance tasks on the file backed tenants selected by `filter`, recording when each was last
+    /// maintained in the master database.
+    ///
+    /// Tenants are picked from the master registry, least recently maintained first, and opened on their own
+    /// connection so the cache is left alone. Read-only and in-memory tenants are never maintained. A `VACUUM` fails
+    /// with `SQLITE_BUSY` while another connection is reading the tenant, in which case the tenant is reported as
+    /// failed and picked again on the next run. Rate limiting sleeps on the calling thread.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "tenant.maintenance", skip_all))]
+    pub fn run_maintenance(
+        &mut self,
+        filter: TenantFilter,
+        options: MaintenanceOptions,
+    ) -> SQLResult<MaintenanceReport, MultiTenantError>
+    {
+        let maintained_before = options
+            .min_interval
+            .map(|interval| unix_millis(SystemTime::now().checked_sub(interval).unwrap_or(SystemTime::UNIX_EPOCH)));
+
+        let mut statement = self.master_db.prepare(SqlStatement::SelectMaintenanceCandidates.as_str())?;
+
+        let tenants: Vec<TenantRecord> = statement
+            .query_map(params![maintained_before], Self::tenant_record_from_row)?
+            .collect::<SQLResult<Vec<_>>>()?
+            .into_iter()
+            .filter(|tenant| filter.matches(tenant))
+            .collect();
+
+        drop(statement);
+
+        let mut report = MaintenanceReport::default();
+        let started = Instant::now();
+        let mut pages = 0;
+
+        for tenant in tenants {
+            let tenant_id = tenant.tenant_id;
+
+            if self.read_only_tenants.contains(&tenant_id) {
+                report.skipped.push(tenant_id);
+                continue;
+            }
+
+            if options
+                .max_tenants
+                .is_some_and(|max| report.maintained.len() + report.failed.len() >= max)
+            {
+                report.deferred.push(tenant_id);
+                continue;
+            }
+
+            let Some(path) = tenant.path else {
+                continue;
+            };
+
+            if !path.exists() {
+                report.failed.push((
+                    tenant_id.clone(),
+                    MultiTenantError::TenantFileMissing(format!("'{}' at {}", tenant_id, path.display())),
+                ));
+                continue;
+            }
+
+            let connection = match open_existing_file(&path) {
+                Ok(connection) => connection,
+                Err(err) => {
+                    report.failed.push((tenant_id, err.into()));
+                    continue;
+                }
+            };
+
+            match Self::maintain_tenant(&connection, &options) {
+                Ok(None) => report.skipped.push(tenant_id),
+                Ok(Some(stats)) => {
+                    pages += stats.page_count_before;
+
+                    self.master_db
+                        .execute(SqlStatement::UpdateLastMaintained.as_str(), params![tenant_id])?;
+
+                    debug!("Maintained ({}) tenant: {:?}", tenant_id, stats);
+                    report.maintained.push((tenant_id, stats));
+                }
+                Err(err) => {
+                    warn!("Maintenance failed for ({}) tenant: {}", tenant_id, err);
+                    report.failed.push((tenant_id, err));
+                }
+            }
+
+            drop(connection);
+
+            if let Some(max_pages_per_second) = options.max_pages_per_second.filter(|max| *max > 0) {
+                let budget = Duration::from_secs_f64(pages as f64 / max_pages_per_second as f64);
+
+                if let Some(wait) = budget.checked_sub(started.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+        }
+
+        info!(
+            "Maintenance finished: {} maintained, {} failed, {} skipped, {} deferred.",
+            report.maintained.len(),
+            report.failed.len(),
+            report.skipped.len(),
+            report.deferred.len()
+        );
+
+        Ok(report)
+    }
+
+    /// Gets when a tenant was last maintained by `run_maintenance`, as a UTC timestamp. `None` if it never was.
+    pub fn last_maintained_at(&self, tenant_id: &str) -> SQLResult<Option<String>, MultiTenantError>
+    {
+        self.master_db
+            .query_row(SqlStatement::SelectLastMaintained.as_str(), params![tenant_id], |row| {
+                row.get(0)
+            })
+            .optional()?
+            .ok_or_else(|| MultiTenantError::TenantNotFound(tenant_id.to_string()))
+    }
+
+    /// Runs the tasks on a tenant, or returns `None` if its freelist is below `min_freelist_ratio`.
+    fn maintain_tenant(
+        connection: &Connection,
+        options: &MaintenanceOptions,
+    ) -> SQLResult<Option<MaintenanceStats>, MultiTenantError>
+    {
+        let (page_count_before, freelist_count_before) = Self::page_counts(connection)?;
+
+        if let Some(min_ratio) = options.min_freelist_ratio {
+            if page_count_before == 0 || (freelist_count_before as f64 / page_count_before as f64) <= min_ratio {
+                return Ok(None);
+            }
+        }
+
+        for task in &options.tasks {
+            connection.execute_batch(&task.sql())?;
+        }
+
+        let (page_count_after, freelist_count_after) = Self::page_counts(connection)?;
+
+        Ok(Some(MaintenanceStats {
+            page_count_before,
+            page_count_after,
+            freelist_count_before,
+            freelist_count_after,
+        }))
+    }
+
+    fn page_counts(connection: &Connection) -> SQLResult<(u64, u64)>
+    {
+        let page_count = connection.query_row("PRAGMA page_count;", [], |row| row.get(0))?;
+        let freelist_count = connection.query_row("PRAGMA freelist_count;", [], |row| row.get(0))?;
+
+        Ok((page_count, freelist_count))
+    }
+}
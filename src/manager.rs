@@ -1,26 +1,63 @@
-extern crate lru;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
-use std::num::NonZeroUsize;
-use std::path::{Path, PathBuf};
-use std::sync::Arc;
-
-use flexi_logger::{FileSpec, Logger};
 use log::{debug, error, info, warn};
-use lru::LruCache;
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension, Row};
 
-use crate::config::Configuration;
+use crate::cache::{CachePolicy, CacheStats, TenantCache};
+use crate::config::{Configuration, DEFAULT_CACHE_CAP, DEFAULT_STATS_FLUSH_INTERVAL};
 use crate::error::{MultiTenantError, SQLResult};
+use crate::memory::MemoryAnchor;
+use crate::observer::{self, TenantObserver};
+use crate::query_trace::{QueryTrace, QueryTraceOptions};
+use crate::quota::TenantQuota;
+use crate::recovery::MissingFilePolicy;
 use crate::statements::SqlStatement;
-use crate::tenant::TenantConnection;
-
-type TenantId = String;
+use crate::stats::PendingAccess;
+use crate::telemetry;
+use crate::tenant::{TenantConnection, TenantRecord};
+use crate::tenant_id::TenantId;
+use crate::warmup::WarmedConnection;
 
 pub struct MultiTenantManager
 {
     /// The master database manages all the data for other tenants such as lookups, permissions, etc.
     pub(crate) master_db: Connection,
-    pub(crate) cache: LruCache<TenantId, TenantConnection>,
+    pub(crate) cache: Box<dyn TenantCache>,
+    /// Read-only connections, cached apart from `cache` so a tenant can have both open.
+    pub(crate) readonly_cache: Box<dyn TenantCache>,
+    /// Tenants with the `ReadOnly` access mode, loaded from the master so `get_connection` does not query it each time.
+    pub(crate) read_only_tenants: HashSet<String>,
+    /// Quotas of the tenants loaded from the database, so `get_connection` does not query the master each time.
+    pub(crate) quotas: HashMap<String, TenantQuota>,
+    /// Connections evicted from the cache while a caller still held them, tracked until they are dropped.
+    pub(crate) detached: Vec<(String, Weak<Connection>)>,
+    pub(crate) max_open_connections: Option<usize>,
+    pub(crate) stats: CacheStats,
+    /// When each cached tenant was last handed out, used by `tick` to find idle connections.
+    pub(crate) last_access: HashMap<String, Instant>,
+    pub(crate) idle_timeout: Option<Duration>,
+    /// Connections being opened by `warm_cache` threads.
+    pub(crate) warmups: Vec<Receiver<WarmedConnection>>,
+    /// Access stats not yet written to the master database, see `flush_stats`.
+    pub(crate) pending_access: HashMap<String, PendingAccess>,
+    pub(crate) stats_flush_interval: Duration,
+    pub(crate) last_stats_flush: Instant,
+    pub(crate) query_trace: Option<QueryTraceOptions>,
+    /// Tenants traced differently from `query_trace`, see `trace_queries`.
+    pub(crate) query_trace_overrides: HashMap<String, Option<QueryTraceOptions>>,
+    /// Traces of the tenants that had a connection opened while traced.
+    pub(crate) query_traces: HashMap<String, Arc<QueryTrace>>,
+    pub(crate) missing_file_policy: MissingFilePolicy,
+    /// Keeps the database of each in-memory tenant alive across cache evictions.
+    pub(crate) memory_anchors: HashMap<String, MemoryAnchor>,
+    /// Notified of tenant lifecycle events, see `add_observer`.
+    pub(crate) observers: Vec<Arc<dyn TenantObserver>>,
+    /// Where the next sampled `health_check` starts its integrity checks.
+    pub(crate) integrity_cursor: usize,
 }
 
 impl MultiTenantManager
@@ -36,48 +73,60 @@ impl MultiTenantManager
 
         MultiTenantManager::init_master_db(&mut master_db).expect("Failed to init master database");
 
-        // Set up the logger settings for the manager
-        if let Some(log_level) = config.log_level {
-            // If log_level is Some, initialize the logger
-            match Logger::try_with_str(log_level.as_str()) {
-                Ok(logger_builder) => {
-                    match logger_builder
-                        .log_to_file(FileSpec::default().directory(config.log_dir.unwrap_or(PathBuf::from("logs"))))
-                        .duplicate_to_stdout(log_level.as_dup())
-                        .format(flexi_logger::detailed_format)
-                        .start()
-                    {
-                        Ok(logger_handle) => Some(logger_handle),
-                        Err(err) => {
-                            eprintln!("Error starting logger: {}", err);
-                            None
-                        }
-                    }
-                }
-                Err(err) => {
-                    eprintln!("Error creating logger: {}", err);
-                    None
-                }
-            }
-        } else {
-            None
-        };
-
         info!("MultiTenantManager Initialized");
 
-        Ok(Self {
+        let cache_cap = config.lru_cache_cap.unwrap_or(DEFAULT_CACHE_CAP).max(1);
+
+        let mut manager = Self {
             master_db,
-            cache: LruCache::new(NonZeroUsize::new(config.lru_cache_cap.unwrap_or(150)).unwrap()),
-        })
+            cache: config.cache_policy.unwrap_or_default().build(cache_cap),
+            readonly_cache: CachePolicy::Lru.build(cache_cap),
+            read_only_tenants: HashSet::new(),
+            quotas: HashMap::new(),
+            detached: Vec::new(),
+            max_open_connections: config.max_open_connections,
+            stats: CacheStats::default(),
+            last_access: HashMap::new(),
+            idle_timeout: config.idle_timeout,
+            warmups: Vec::new(),
+            pending_access: HashMap::new(),
+            stats_flush_interval: config.stats_flush_interval.unwrap_or(DEFAULT_STATS_FLUSH_INTERVAL),
+            last_stats_flush: Instant::now(),
+            query_trace: config.query_trace,
+            query_trace_overrides: HashMap::new(),
+            query_traces: HashMap::new(),
+            missing_file_policy: config.missing_file_policy.unwrap_or_default(),
+            memory_anchors: HashMap::new(),
+            observers: Vec::new(),
+            integrity_cursor: 0,
+        };
+
+        for tenant in manager.pinned_tenants().expect("Failed to load pinned tenants") {
+            manager.cache.pin(&tenant.tenant_id);
+        }
+
+        manager.read_only_tenants = manager.load_read_only_tenants().expect("Failed to load read-only tenants");
+
+        Ok(manager)
     }
 
     /// Adds a new tenant to the manager
     ///
-    /// `tenant_id` - used to track a connection to a sqlite db. ID generation should be handled by the library user.
+    /// `tenant_id` - used to track a connection to a sqlite db. ID generation should be handled by the library user,
+    /// and the id must pass `TenantId` validation.
     ///
-    /// `path` - to the db file. If `None` is passed, the tenant will be created as an in-memory database.
+    /// `path` - to the db file. If `None` is passed, the tenant will be created as an in-memory database, which keeps
+    /// its data when evicted from the cache until the tenant is removed or the manager is dropped.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "tenant.add", skip_all, fields(tenant_id = %tenant_id)))]
     pub fn add_tenant(&mut self, tenant_id: &str, path: Option<PathBuf>) -> SQLResult<(), MultiTenantError>
     {
+        TenantId::validate(tenant_id)?;
+        observer::veto(&self.observers, |observer| observer.before_add(tenant_id, path.as_deref()))?;
+        self.reserve_cache_slot(tenant_id)?;
+        self.reserve_connection()?;
+
+        let started = Instant::now();
+
         // Begin a transaction
         let tx = self.master_db.transaction()?;
 
@@ -98,18 +147,50 @@ impl MultiTenantManager
             )));
         }
 
-        let connection = TenantConnection::open(path.clone())?;
-        self.cache.put(tenant_id.to_string(), connection);
+        telemetry::master_query("add_tenant", started.elapsed());
+
+        let connection = match &path {
+            Some(path) => TenantConnection::open(Some(path))?,
+            None => self.open_memory_tenant(tenant_id)?,
+        };
+        self.cache_connection(tenant_id, connection);
+        telemetry::tenant_added();
+
+        observer::notify(&self.observers, "add", tenant_id, |observer| {
+            observer.on_added(tenant_id, path.as_deref())
+        });
 
         info!("Added ({}) tenant.", tenant_id);
 
         Ok(())
     }
 
-    /// Removes a tenant connection from the manager
+    /// Removes a tenant from the manager, closing its cached connection if it has one.
+    ///
+    /// Fails without changing anything while a caller still holds one of the tenant's cached connections.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "tenant.remove", skip_all, fields(tenant_id = %tenant_id)))]
     pub fn remove_tenant(&mut self, tenant_id: &str) -> SQLResult<(), MultiTenantError>
     {
-        if let Some(tenant) = self.cache.pop(tenant_id) {
+        if !self.cache.contains(tenant_id) && self.get_tenant(tenant_id)?.is_none() {
+            error!("Attempted to delete tenant ({}) that does not exist.", tenant_id);
+            return Err(MultiTenantError::TenantNotFound(tenant_id.to_string()));
+        }
+
+        // Checked before anything is removed, so a failed removal leaves the caches as they were.
+        let borrowed = |connection: Option<&TenantConnection>| {
+            connection.is_some_and(|connection| Arc::strong_count(&connection.connection) > 1)
+        };
+
+        if borrowed(self.cache.peek(tenant_id)) || borrowed(self.readonly_cache.peek(tenant_id)) {
+            return Err(MultiTenantError::DatabaseError(format!(
+                "Failed to unwrap Arc for {}",
+                tenant_id
+            )));
+        }
+
+        observer::veto(&self.observers, |observer| observer.before_remove(tenant_id))?;
+
+        if let Some(tenant) = self.cache.remove(tenant_id) {
             // Close the connection held within the Arc
             Arc::try_unwrap(tenant.connection)
                 .map_err(|_| MultiTenantError::DatabaseError(format!("Failed to unwrap Arc for {}", tenant_id)))?
@@ -118,43 +199,212 @@ impl MultiTenantManager
                     MultiTenantError::DatabaseError(format!("Failed to close connection for {}: {:?}", tenant_id, e))
                 })?;
 
-            // Begin a transaction
-            let tx = self.master_db.transaction()?;
+            telemetry::tenants_closed(1);
+            self.report_open_connections();
+        }
 
-            tx.execute(SqlStatement::DeleteRemoveTenant.as_str(), params![tenant_id])?;
+        self.unregister_tenant(tenant_id)?;
+        telemetry::tenant_removed();
 
-            if let Err(err) = tx.commit() {
-                debug!("Failed to commit transaction: {}", err);
-                return Err(MultiTenantError::DatabaseError(format!(
-                    "Failed to commit transaction: {}",
-                    err
-                )));
-            }
+        debug!("Deleted ({}) tenant.", tenant_id);
+        Ok(())
+    }
 
-            debug!("Deleted ({}) tenant.", tenant_id);
-            Ok(())
-        } else {
-            error!("Attempted to delete tenant ({}) that does not exist.", tenant_id);
-            Err(MultiTenantError::TenantNotFound(tenant_id.to_string()))
+    /// Deletes every master row of a tenant in one transaction, and forgets what the manager tracks about it.
+    pub(crate) fn unregister_tenant(&mut self, tenant_id: &str) -> SQLResult<(), MultiTenantError>
+    {
+        let started = Instant::now();
+
+        // Begin a transaction
+        let tx = self.master_db.transaction()?;
+
+        tx.execute(SqlStatement::DeleteRemoveTenant.as_str(), params![tenant_id])?;
+
+        for statement in SqlStatement::tenant_deletes() {
+            tx.execute(statement.as_str(), params![tenant_id])?;
+        }
+
+        if let Err(err) = tx.commit() {
+            debug!("Failed to commit transaction: {}", err);
+            return Err(MultiTenantError::DatabaseError(format!(
+                "Failed to commit transaction: {}",
+                err
+            )));
         }
+
+        telemetry::master_query("remove_tenant", started.elapsed());
+
+        self.quotas.remove(tenant_id);
+        self.last_access.remove(tenant_id);
+        self.pending_access.remove(tenant_id);
+        self.query_trace_overrides.remove(tenant_id);
+        self.query_traces.remove(tenant_id);
+        self.read_only_tenants.remove(tenant_id);
+        self.memory_anchors.remove(tenant_id);
+        self.drop_readonly_connection(tenant_id);
+
+        observer::notify(&self.observers, "remove", tenant_id, |observer| {
+            observer.on_removed(tenant_id)
+        });
+
+        Ok(())
+    }
+
+    /// Renames a tenant, moving its master rows and cached connection over to `new_id`.
+    ///
+    /// All master updates happen in a single transaction. Returns `TenantAlreadyExists` if `new_id` is taken, and
+    /// `InvalidTenantId` if it fails `TenantId` validation.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "tenant.rename", skip_all, fields(old_id = %old_id, new_id = %new_id))
+    )]
+    pub fn rename_tenant(&mut self, old_id: &str, new_id: &str) -> SQLResult<(), MultiTenantError>
+    {
+        TenantId::validate(new_id)?;
+
+        let started = Instant::now();
+        let tx = self.master_db.transaction()?;
+
+        let taken: bool = tx.query_row(SqlStatement::SelectTenantExists.as_str(), params![new_id], |row| row.get(0))?;
+
+        if taken {
+            error!(
+                "Attempted to rename tenant ({}) to ({}) which already exists.",
+                old_id, new_id
+            );
+            return Err(MultiTenantError::TenantAlreadyExists(new_id.to_string()));
+        }
+
+        let renamed = tx.execute(SqlStatement::UpdateRenameTenant.as_str(), params![new_id, old_id])?;
+
+        if renamed == 0 {
+            error!("Attempted to rename tenant ({}) that does not exist.", old_id);
+            return Err(MultiTenantError::TenantNotFound(old_id.to_string()));
+        }
+
+        // Rolled back when the transaction is dropped.
+        observer::veto(&self.observers, |observer| observer.before_rename(old_id, new_id))?;
+
+        for statement in SqlStatement::tenant_renames() {
+            tx.execute(statement.as_str(), params![new_id, old_id])?;
+        }
+
+        if let Err(err) = tx.commit() {
+            debug!("Failed to commit transaction: {}", err);
+            return Err(MultiTenantError::DatabaseError(format!(
+                "Failed to commit transaction: {}",
+                err
+            )));
+        }
+
+        telemetry::master_query("rename_tenant", started.elapsed());
+
+        if let Some(connection) = self.cache.remove(old_id) {
+            self.cache.insert(new_id.to_string(), connection);
+        }
+
+        if let Some(connection) = self.readonly_cache.remove(old_id) {
+            self.readonly_cache.insert(new_id.to_string(), connection);
+        }
+
+        if self.read_only_tenants.remove(old_id) {
+            self.read_only_tenants.insert(new_id.to_string());
+        }
+
+        for (tenant_id, _) in self.detached.iter_mut().filter(|(tenant_id, _)| tenant_id == old_id) {
+            *tenant_id = new_id.to_string();
+        }
+
+        if let Some(pending) = self.pending_access.remove(old_id) {
+            self.pending_access.insert(new_id.to_string(), pending);
+        }
+
+        if let Some(last_access) = self.last_access.remove(old_id) {
+            self.last_access.insert(new_id.to_string(), last_access);
+        }
+
+        if let Some(anchor) = self.memory_anchors.remove(old_id) {
+            self.memory_anchors.insert(new_id.to_string(), anchor);
+        }
+
+        if let Some(quota) = self.quotas.remove(old_id) {
+            self.quotas.insert(new_id.to_string(), quota);
+        }
+
+        if let Some(options) = self.query_trace_overrides.remove(old_id) {
+            self.query_trace_overrides.insert(new_id.to_string(), options);
+        }
+
+        if let Some(trace) = self.query_traces.remove(old_id) {
+            trace.rename(new_id);
+            self.query_traces.insert(new_id.to_string(), trace);
+        }
+
+        info!("Renamed ({}) tenant to ({}).", old_id, new_id);
+
+        observer::notify(&self.observers, "rename", old_id, |observer| {
+            observer.on_renamed(old_id, new_id)
+        });
+
+        Ok(())
     }
 
     /// Get a tenant connection based on id
+    ///
+    /// Returns `QuotaExceeded` if the tenant already has as many outstanding connections as its quota allows. The
+    /// connection is read-only if the tenant has the `ReadOnly` access mode, see `set_access_mode`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "tenant.get_connection", skip_all, fields(tenant_id = %tenant_id))
+    )]
     pub fn get_connection(&mut self, tenant_id: &str) -> SQLResult<Option<TenantConnection>, MultiTenantError>
     {
-        if let Some(connection) = self.cache.get_mut(tenant_id) {
+        match self.fetch_connection(tenant_id)? {
+            Some(connection) => {
+                self.check_connection_quota(tenant_id, &connection)?;
+                self.record_access(tenant_id);
+                Ok(Some(connection))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Gets a tenant connection from the cache, or loads it from the database, without checking quotas.
+    ///
+    /// Tenants with the `ReadOnly` access mode get a read-only connection.
+    pub(crate) fn fetch_connection(&mut self, tenant_id: &str) -> SQLResult<Option<TenantConnection>, MultiTenantError>
+    {
+        if self.read_only_tenants.contains(tenant_id) {
+            return self.fetch_readonly_connection(tenant_id);
+        }
+
+        if !self.warmups.is_empty() {
+            self.drain_warmup();
+        }
+
+        if let Some(connection) = self.cache.get(tenant_id) {
             debug!("Retrieving ({}) sqlite connection from cache.", tenant_id);
-            Ok(Some(connection.clone()))
+            let connection = connection.clone();
+            self.stats.hits += 1;
+            telemetry::cache_hit();
+            self.last_access.insert(tenant_id.to_string(), Instant::now());
+            Ok(Some(connection))
         } else {
+            self.stats.misses += 1;
+            telemetry::cache_miss(tenant_id);
             warn!(
                 "Attempted to retrieve ({}) sqlite connection but it was not found in cache... searching database...",
                 tenant_id
             );
 
+            self.reserve_cache_slot(tenant_id)?;
+            self.reserve_connection()?;
+
             // If connection not found in cache, search the database
-            match Self::load_tenant_from_db(&mut self.master_db, tenant_id) {
+            match self.load_tenant_from_db(tenant_id) {
                 Ok(Some(connection)) => {
-                    self.cache.put(tenant_id.to_string(), connection.clone());
+                    self.apply_quotas(tenant_id, &connection)?;
+                    self.cache_connection(tenant_id, connection.clone());
                     debug!("Retrieving ({}) sqlite connection from database.", tenant_id);
                     Ok(Some(connection))
                 }
@@ -164,6 +414,13 @@ impl MultiTenantManager
         }
     }
 
+    /// Like `fetch_connection`, but a tenant that is not registered is a `TenantNotFound` error.
+    pub(crate) fn tenant_connection(&mut self, tenant_id: &str) -> SQLResult<TenantConnection, MultiTenantError>
+    {
+        self.fetch_connection(tenant_id)?
+            .ok_or_else(|| MultiTenantError::TenantNotFound(tenant_id.to_string()))
+    }
+
     /// Gets the current amount of tenants in the database.
     pub fn tenant_count(&self) -> usize
     {
@@ -175,34 +432,101 @@ impl MultiTenantManager
             })
     }
 
+    /// Lists every tenant registered in the master database, in the order they were added.
+    pub fn list_tenants(&self) -> SQLResult<Vec<TenantRecord>, MultiTenantError>
+    {
+        let mut statement = self.master_db.prepare(SqlStatement::SelectTenantList.as_str())?;
+
+        let tenants = statement
+            .query_map([], Self::tenant_record_from_row)?
+            .collect::<SQLResult<Vec<_>>>()?;
+
+        Ok(tenants)
+    }
+
+    /// Looks up a single tenant in the master database.
+    pub fn get_tenant(&self, tenant_id: &str) -> SQLResult<Option<TenantRecord>, MultiTenantError>
+    {
+        Ok(self
+            .master_db
+            .query_row(
+                SqlStatement::SelectTenantRecord.as_str(),
+                params![tenant_id],
+                Self::tenant_record_from_row,
+            )
+            .optional()?)
+    }
+
+    fn load_read_only_tenants(&self) -> SQLResult<HashSet<String>, MultiTenantError>
+    {
+        let mut statement = self.master_db.prepare(SqlStatement::SelectReadOnlyTenants.as_str())?;
+
+        let tenants = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<SQLResult<HashSet<_>>>()?;
+
+        Ok(tenants)
+    }
+
+    /// Maps a row of `tenant_id, tenant_path, tenant_has_path, created_at`.
+    pub(crate) fn tenant_record_from_row(row: &Row) -> SQLResult<TenantRecord>
+    {
+        let path: Option<String> = row.get(1)?;
+        let has_path: bool = row.get(2)?;
+
+        Ok(TenantRecord {
+            tenant_id: row.get(0)?,
+            path: if has_path { path.map(PathBuf::from) } else { None },
+            created_at: row.get(3)?,
+        })
+    }
+
     /// Creates the master database if none exist yet.
     fn init_master_db(conn: &mut Connection) -> SQLResult<()>
     {
         let tx = conn.transaction()?;
 
-        tx.execute(SqlStatement::CreateMasterDb.as_str(), [])?;
+        for statement in SqlStatement::schema() {
+            tx.execute(statement.as_str(), [])?;
+        }
+
+        for (table, column, statement) in SqlStatement::column_upgrades() {
+            let exists: bool = tx.query_row(
+                "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2);",
+                params![table, column],
+                |row| row.get(0),
+            )?;
+
+            if !exists {
+                debug!("Upgrading master database, adding {}.{}", table, column);
+                tx.execute(statement.as_str(), [])?;
+            }
+        }
+
         tx.commit()?;
 
         Ok(())
     }
 
     /// Load a tenant connection from the database
-    fn load_tenant_from_db(
-        master_db: &mut Connection,
-        tenant_id: &str,
-    ) -> SQLResult<Option<TenantConnection>, MultiTenantError>
-    {
-        let mut statement = master_db.prepare(SqlStatement::SelectTenant.as_str())?;
-        let mut rows = statement.query(params![tenant_id])?;
-
-        if let Some(row) = rows.next()? {
-            let path: Option<String> = row.get(0)?;
-            let has_path: bool = row.get(1)?;
-
-            let connection = if has_path {
-                TenantConnection::open(path.map(PathBuf::from))?
-            } else {
-                TenantConnection::open(None::<&Path>)?
+    ///
+    /// The file of a registered tenant is never created, a missing one goes through the `MissingFilePolicy`.
+    fn load_tenant_from_db(&mut self, tenant_id: &str) -> SQLResult<Option<TenantConnection>, MultiTenantError>
+    {
+        let started = Instant::now();
+        let row: Option<(Option<String>, bool)> = self
+            .master_db
+            .query_row(SqlStatement::SelectTenant.as_str(), params![tenant_id], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()?;
+
+        telemetry::master_query("load_tenant", started.elapsed());
+
+        if let Some((path, has_path)) = row {
+            let connection = match path.filter(|_| has_path).map(PathBuf::from) {
+                Some(path) => self.open_tenant_file(tenant_id, &path, |path| TenantConnection::open_existing(path))?,
+                None => self.open_memory_tenant(tenant_id)?,
             };
 
             debug!("found {} in the database...", tenant_id);
@@ -214,3 +538,13 @@ impl MultiTenantManager
         }
     }
 }
+
+impl Drop for MultiTenantManager
+{
+    fn drop(&mut self)
+    {
+        if let Err(err) = self.flush_stats() {
+            error!("Failed to flush tenant access stats: {}", err);
+        }
+    }
+}
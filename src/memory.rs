@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::debug;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+use crate::tenant::TenantConnection;
+
+/// Numbers the in-memory databases of every manager in the process, since shared-cache names are process wide.
+static NEXT_MEMORY_DATABASE: AtomicU64 = AtomicU64::new(0);
+
+/// Keeps the shared-cache in-memory database of a tenant alive while none of its connections are open.
+///
+/// SQLite frees a named in-memory database once its last connection closes, so the anchor is never handed out and
+/// is only closed when the tenant is removed or the manager is dropped.
+pub(crate) struct MemoryAnchor
+{
+    pub(crate) uri: String,
+    _connection: Connection,
+}
+
+impl MemoryAnchor
+{
+    fn open() -> SQLResult<Self>
+    {
+        let uri = format!(
+            "file:sqlite-tenant-memory-{}?mode=memory&cache=shared",
+            NEXT_MEMORY_DATABASE.fetch_add(1, Ordering::Relaxed)
+        );
+
+        Ok(Self {
+            _connection: open_memory_database(&uri)?,
+            uri,
+        })
+    }
+}
+
+/// Opens another connection to a shared-cache in-memory database.
+pub(crate) fn open_memory_database(uri: &str) -> SQLResult<Connection>
+{
+    Connection::open_with_flags(
+        uri,
+        OpenFlags::SQLITE_OPEN_READ_WRITE
+            | OpenFlags::SQLITE_OPEN_CREATE
+            | OpenFlags::SQLITE_OPEN_URI
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+}
+
+impl MultiTenantManager
+{
+    /// Opens a connection to an in-memory tenant, creating its database the first time.
+    ///
+    /// Every connection of a tenant shares the same database, which outlives cache evictions until the tenant is
+    /// removed or the manager is dropped. Nothing is written to disk, so a new manager starts in-memory tenants empty.
+    pub(crate) fn open_memory_tenant(&mut self, tenant_id: &str) -> SQLResult<TenantConnection, MultiTenantError>
+    {
+        let uri = self.memory_anchor(tenant_id)?.uri.clone();
+
+        Ok(TenantConnection::open_shared_memory(&uri)?)
+    }
+
+    /// Opens another connection to an in-memory tenant without creating its database, failing if this manager has not
+    /// opened it yet.
+    pub(crate) fn open_anchored_memory_tenant(&self, tenant_id: &str) -> SQLResult<TenantConnection, MultiTenantError>
+    {
+        match self.memory_anchors.get(tenant_id) {
+            Some(anchor) => Ok(TenantConnection::open_shared_memory(&anchor.uri)?),
+            None => Err(MultiTenantError::DatabaseError(format!(
+                "Tenant '{}' is in-memory and has no database open in this manager",
+                tenant_id
+            ))),
+        }
+    }
+
+    /// Gets the anchor of an in-memory tenant, opening its database if it has none yet.
+    pub(crate) fn memory_anchor(&mut self, tenant_id: &str) -> SQLResult<&MemoryAnchor, MultiTenantError>
+    {
+        if !self.memory_anchors.contains_key(tenant_id) {
+            let anchor = MemoryAnchor::open()?;
+            debug!("Opened ({}) in-memory database {}.", tenant_id, anchor.uri);
+            self.memory_anchors.insert(tenant_id.to_string(), anchor);
+        }
+
+        Ok(&self.memory_anchors[tenant_id])
+    }
+}
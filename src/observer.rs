@@ -0,0 +1,143 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use log::warn;
+
+use crate::access::AccessMode;
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+use crate::telemetry;
+
+/// Why a connection left the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionReason
+{
+    /// Made room for another tenant. Borrowed connections are never evicted this way.
+    Idle,
+    /// The cache policy expired it, such as `CachePolicy::Ttl`.
+    Expired,
+    /// `tick` closed it after `Configuration::idle_timeout`.
+    Reaped,
+}
+
+impl EvictionReason
+{
+    pub(crate) fn as_str(&self) -> &'static str
+    {
+        match self {
+            EvictionReason::Idle => "idle",
+            EvictionReason::Expired => "expired",
+            EvictionReason::Reaped => "reaped",
+        }
+    }
+}
+
+/// Callbacks fired on the lifecycle of tenants, registered with `MultiTenantManager::add_observer`.
+///
+/// Every callback does nothing by default. The `before_*` callbacks run once the arguments are validated and before
+/// anything changes, and returning an error vetoes the operation, which fails with that error. The first observer to
+/// veto stops the rest from being called. The `on_*` callbacks run after the change is made, so their errors are only
+/// logged.
+///
+/// Callbacks run on the thread using the manager, in the order observers were added, so slow work such as updating
+/// a search index is best sent to a queue.
+pub trait TenantObserver: Send + Sync
+{
+    fn before_add(&self, _tenant_id: &str, _path: Option<&Path>) -> SQLResult<(), MultiTenantError>
+    {
+        Ok(())
+    }
+
+    fn on_added(&self, _tenant_id: &str, _path: Option<&Path>) -> SQLResult<(), MultiTenantError>
+    {
+        Ok(())
+    }
+
+    fn before_remove(&self, _tenant_id: &str) -> SQLResult<(), MultiTenantError>
+    {
+        Ok(())
+    }
+
+    /// Also fired when `reconcile` unregisters a tenant whose file is missing.
+    fn on_removed(&self, _tenant_id: &str) -> SQLResult<(), MultiTenantError>
+    {
+        Ok(())
+    }
+
+    fn before_rename(&self, _old_id: &str, _new_id: &str) -> SQLResult<(), MultiTenantError>
+    {
+        Ok(())
+    }
+
+    fn on_renamed(&self, _old_id: &str, _new_id: &str) -> SQLResult<(), MultiTenantError>
+    {
+        Ok(())
+    }
+
+    fn before_access_mode_change(&self, _tenant_id: &str, _mode: AccessMode) -> SQLResult<(), MultiTenantError>
+    {
+        Ok(())
+    }
+
+    fn on_access_mode_changed(&self, _tenant_id: &str, _mode: AccessMode) -> SQLResult<(), MultiTenantError>
+    {
+        Ok(())
+    }
+
+    /// A connection was opened and added to the cache.
+    fn on_opened(&self, _tenant_id: &str, _read_only: bool) -> SQLResult<(), MultiTenantError>
+    {
+        Ok(())
+    }
+
+    fn on_evicted(&self, _tenant_id: &str, _reason: EvictionReason) -> SQLResult<(), MultiTenantError>
+    {
+        Ok(())
+    }
+
+    /// The `MissingFilePolicy` recreated or restored the missing file of a tenant.
+    fn on_file_recovered(&self, _tenant_id: &str, _path: &Path) -> SQLResult<(), MultiTenantError>
+    {
+        Ok(())
+    }
+}
+
+/// Runs a `before_*` callback on every observer, stopping at the first veto.
+pub(crate) fn veto<F>(observers: &[Arc<dyn TenantObserver>], f: F) -> SQLResult<(), MultiTenantError>
+where
+    F: Fn(&dyn TenantObserver) -> SQLResult<(), MultiTenantError>,
+{
+    observers.iter().try_for_each(|observer| f(observer.as_ref()))
+}
+
+/// Runs an `on_*` callback on every observer, logging the ones that fail.
+pub(crate) fn notify<F>(observers: &[Arc<dyn TenantObserver>], event: &str, tenant_id: &str, f: F)
+where
+    F: Fn(&dyn TenantObserver) -> SQLResult<(), MultiTenantError>,
+{
+    for observer in observers {
+        if let Err(err) = f(observer.as_ref()) {
+            warn!("Tenant observer failed on {} of ({}) tenant: {}", event, tenant_id, err);
+        }
+    }
+}
+
+impl MultiTenantManager
+{
+    /// Registers an observer of tenant lifecycle events, see `TenantObserver`.
+    pub fn add_observer(&mut self, observer: Arc<dyn TenantObserver>)
+    {
+        self.observers.push(observer);
+    }
+
+    /// Counts an eviction and tells the observers about it.
+    pub(crate) fn record_eviction(&mut self, tenant_id: &str, reason: EvictionReason)
+    {
+        self.stats.evictions += 1;
+        telemetry::eviction(reason.as_str());
+
+        notify(&self.observers, "eviction", tenant_id, |observer| {
+            observer.on_evicted(tenant_id, reason)
+        });
+    }
+}
@@ -3,8 +3,31 @@
 pub use rusqlite::*;
 
 // Export other crates
+pub use crate::access::*;
+pub use crate::cache::*;
 pub use crate::config::*;
 pub use crate::error::*;
+pub use crate::fanout::*;
+pub use crate::federated::*;
+pub use crate::health::*;
+#[cfg(feature = "http-admin")]
+pub use crate::http_admin::*;
 pub use crate::logger::*;
+pub use crate::maintenance::*;
 pub use crate::manager::*;
+pub use crate::observer::*;
+pub use crate::query_trace::*;
+pub use crate::quota::*;
+pub use crate::reaper::*;
+pub use crate::reconcile::*;
+pub use crate::recovery::*;
+pub use crate::shutdown::*;
+pub use crate::statements::quote_identifier;
+pub use crate::stats::*;
+#[cfg(feature = "metrics")]
+pub use crate::telemetry::*;
 pub use crate::tenant::*;
+pub use crate::tenant_id::*;
+pub use crate::typed_manager::*;
+pub use crate::usage::*;
+pub use crate::warmup::*;
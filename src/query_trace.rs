@@ -0,0 +1,272 @@
+use std::collections::VecDeque;
+use std::ffi::CStr;
+use std::os::raw::{c_int, c_uint, c_void};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, warn};
+use rusqlite::{ffi, Connection};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+
+/// How statements run against a tenant are traced.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryTraceOptions
+{
+    /// Statements that take longer than this are logged as a warning with the tenant id.
+    /// If `None` is provided, nothing is logged.
+    pub slow_query_threshold: Option<Duration>,
+    /// The amount of recent statements kept for `recent_queries`. `0` keeps none.
+    pub recent_queries: usize,
+}
+
+/// A statement that ran against a tenant, without its bound parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracedQuery
+{
+    pub sql: String,
+    pub duration: Duration,
+}
+
+/// The trace of a single tenant, shared by every connection opened to it.
+///
+/// Each hooked connection owns a strong reference to the trace, handed to SQLite as the context of its profile hook
+/// and released when the connection closes. There is no global registry, so tracing a statement only touches the
+/// trace of its own tenant.
+pub(crate) struct QueryTrace
+{
+    /// `false` while the tenant is not traced, checked before anything else on every statement.
+    enabled: AtomicBool,
+    state: Mutex<TraceState>,
+}
+
+struct TraceState
+{
+    tenant_id: String,
+    options: QueryTraceOptions,
+    recent: VecDeque<TracedQuery>,
+}
+
+impl QueryTrace
+{
+    fn new(tenant_id: &str) -> Arc<Self>
+    {
+        Arc::new(Self {
+            enabled: AtomicBool::new(false),
+            state: Mutex::new(TraceState {
+                tenant_id: tenant_id.to_string(),
+                options: QueryTraceOptions::default(),
+                recent: VecDeque::new(),
+            }),
+        })
+    }
+
+    /// Updates the options of the trace, or stops tracing and forgets the recent statements if `None` is passed.
+    fn set_options(&self, options: Option<QueryTraceOptions>)
+    {
+        if let Ok(mut state) = self.state.lock() {
+            self.enabled.store(options.is_some(), Ordering::Relaxed);
+
+            let options = options.unwrap_or_default();
+            state.recent.truncate(options.recent_queries);
+            state.options = options;
+        }
+    }
+
+    pub(crate) fn rename(&self, tenant_id: &str)
+    {
+        if let Ok(mut state) = self.state.lock() {
+            state.tenant_id = tenant_id.to_string();
+        }
+    }
+
+    fn record(&self, sql: &str, duration: Duration)
+    {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+
+        if state
+            .options
+            .slow_query_threshold
+            .is_some_and(|threshold| duration > threshold)
+        {
+            warn!("Slow query on ({}) tenant took {:?}: {}", state.tenant_id, duration, sql);
+        }
+
+        if state.options.recent_queries == 0 {
+            return;
+        }
+
+        if state.recent.len() >= state.options.recent_queries {
+            state.recent.pop_front();
+        }
+
+        state.recent.push_back(TracedQuery {
+            sql: sql.to_string(),
+            duration,
+        });
+    }
+
+    /// Hooks `connection` up to this trace until the connection closes.
+    ///
+    /// rusqlite's `Connection::profile` can not be used here. It takes a plain `fn(&str, Duration)` without any
+    /// context, so the callback could not tell which tenant ran the statement, and it needs `&mut Connection` while
+    /// tenant connections are shared behind an `Arc`.
+    ///
+    /// Every connection is hooked at most once, setting a second hook would leak the reference held by the first.
+    fn attach(self: &Arc<Self>, connection: &Connection) -> SQLResult<(), MultiTenantError>
+    {
+        let ctx = Arc::into_raw(Arc::clone(self)) as *mut c_void;
+
+        // SAFETY: the handle is only used for this call, while `connection` is borrowed. `ctx` holds a strong reference
+        // that `profile_callback` releases on `SQLITE_TRACE_CLOSE`, after removing the hook, so it never dangles.
+        let code = unsafe {
+            ffi::sqlite3_trace_v2(
+                connection.handle(),
+                (ffi::SQLITE_TRACE_PROFILE | ffi::SQLITE_TRACE_CLOSE) as c_uint,
+                Some(profile_callback),
+                ctx,
+            )
+        };
+
+        if code != ffi::SQLITE_OK {
+            // SAFETY: SQLite did not take the hook, so this is the only use of the reference made above.
+            drop(unsafe { Arc::from_raw(ctx as *const QueryTrace) });
+
+            return Err(MultiTenantError::DatabaseError(format!(
+                "Failed to set the query trace hook: {}",
+                ffi::code_to_str(code)
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Called by SQLite when a statement finishes or the connection closes, with the trace of the tenant as `ctx`.
+unsafe extern "C" fn profile_callback(event: c_uint, ctx: *mut c_void, p: *mut c_void, x: *mut c_void) -> c_int
+{
+    if event == ffi::SQLITE_TRACE_CLOSE as c_uint {
+        // SAFETY: for `SQLITE_TRACE_CLOSE` SQLite passes the connection being closed. The hook is removed before the
+        // reference taken in `attach` is released, so a close that fails with `SQLITE_BUSY` can not call back into it.
+        unsafe {
+            ffi::sqlite3_trace_v2(p as *mut ffi::sqlite3, 0, None, std::ptr::null_mut());
+            drop(Arc::from_raw(ctx as *const QueryTrace));
+        }
+
+        return 0;
+    }
+
+    if event != ffi::SQLITE_TRACE_PROFILE as c_uint {
+        return 0;
+    }
+
+    // SAFETY: `ctx` is the reference taken in `attach`, alive until `SQLITE_TRACE_CLOSE`. For `SQLITE_TRACE_PROFILE`
+    // SQLite passes the finished statement and a pointer to its run time in nanoseconds, both valid for the duration
+    // of the callback.
+    let (trace, sql, nanos) = unsafe {
+        let sql = ffi::sqlite3_sql(p as *mut ffi::sqlite3_stmt);
+
+        if sql.is_null() {
+            return 0;
+        }
+
+        (
+            &*(ctx as *const QueryTrace),
+            CStr::from_ptr(sql).to_string_lossy(),
+            *(x as *const i64),
+        )
+    };
+
+    trace.record(&sql, Duration::from_nanos(nanos.max(0) as u64));
+
+    0
+}
+
+impl MultiTenantManager
+{
+    /// Turns statement tracing on or off for a single tenant, overriding `Configuration::query_trace`.
+    ///
+    /// Passing `None` stops tracing the tenant, even if it is traced by default. A cached connection is updated right
+    /// away, and connections opened later get the same hook. Connections opened just for a fan-out or federated
+    /// query are not traced.
+    pub fn trace_queries(&mut self, tenant_id: &str, options: Option<QueryTraceOptions>) -> SQLResult<(), MultiTenantError>
+    {
+        if self.get_tenant(tenant_id)?.is_none() {
+            return Err(MultiTenantError::TenantNotFound(tenant_id.to_string()));
+        }
+
+        self.query_trace_overrides.insert(tenant_id.to_string(), options);
+
+        let options = self.query_trace_options(tenant_id);
+
+        if let Some(trace) = self.query_traces.get(tenant_id) {
+            trace.set_options(options);
+        } else if options.is_some() {
+            // Cached connections were opened before the tenant had a trace, so none of them are hooked yet.
+            let trace = QueryTrace::new(tenant_id);
+            trace.set_options(options);
+
+            for connection in [self.cache.peek(tenant_id), self.readonly_cache.peek(tenant_id)]
+                .into_iter()
+                .flatten()
+            {
+                trace.attach(&connection.connection)?;
+            }
+
+            self.query_traces.insert(tenant_id.to_string(), trace);
+        }
+
+        debug!("Updated query tracing of ({}) tenant.", tenant_id);
+
+        Ok(())
+    }
+
+    /// Gets the most recent statements run against a tenant, oldest first.
+    ///
+    /// Empty unless the tenant is traced with `QueryTraceOptions::recent_queries` above `0`.
+    pub fn recent_queries(&self, tenant_id: &str) -> Vec<TracedQuery>
+    {
+        self.query_traces
+            .get(tenant_id)
+            .and_then(|trace| trace.state.lock().ok().map(|state| state.recent.iter().cloned().collect()))
+            .unwrap_or_default()
+    }
+
+    /// Hooks a newly opened connection up to the trace of its tenant, if the tenant has one.
+    ///
+    /// A tenant keeps its trace once it has been traced, so connections opened while tracing is turned off are still
+    /// hooked and pick it up again when it is turned back on.
+    pub(crate) fn install_query_trace(&mut self, tenant_id: &str, connection: &Connection)
+        -> SQLResult<(), MultiTenantError>
+    {
+        let options = self.query_trace_options(tenant_id);
+
+        let trace = match self.query_traces.get(tenant_id) {
+            Some(trace) => trace,
+            None if options.is_some() => self
+                .query_traces
+                .entry(tenant_id.to_string())
+                .or_insert_with(|| QueryTrace::new(tenant_id)),
+            None => return Ok(()),
+        };
+
+        trace.set_options(options);
+        trace.attach(connection)
+    }
+
+    fn query_trace_options(&self, tenant_id: &str) -> Option<QueryTraceOptions>
+    {
+        match self.query_trace_overrides.get(tenant_id) {
+            Some(options) => options.clone(),
+            None => self.query_trace.clone(),
+        }
+    }
+}
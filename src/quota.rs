@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+use log::{info, warn};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::error::{MultiTenantError, SQLResult, QUOTA_EXCEEDED_PREFIX};
+use crate::manager::MultiTenantManager;
+use crate::statements::{quote_identifier, quote_literal, SqlStatement};
+use crate::tenant::TenantConnection;
+
+/// The largest `max_page_count` SQLite accepts, used to lift a page quota on an open connection.
+const UNLIMITED_PAGE_COUNT: u64 = 4294967294;
+
+/// Per-tenant limits stored in the master database. `None` means unlimited.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TenantQuota
+{
+    /// The max size of the tenant database in pages, applied with `PRAGMA max_page_count`.
+    pub max_page_count: Option<u64>,
+    /// The max amount of connections from `get_connection` a tenant can have outstanding at once.
+    pub max_connections: Option<usize>,
+}
+
+/// The row usage of a table with a row limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRowUsage
+{
+    pub table: String,
+    pub rows: u64,
+    pub max_rows: u64,
+}
+
+/// The current usage of a tenant measured against its quota.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaUsage
+{
+    pub page_count: u64,
+    pub max_page_count: Option<u64>,
+    /// Connections handed out by `get_connection` that are still held by callers.
+    pub connections: usize,
+    pub max_connections: Option<usize>,
+    pub tables: Vec<TableRowUsage>,
+}
+
+impl MultiTenantManager
+{
+    /// Gets the quota of a tenant, which is unlimited if none was ever set.
+    pub fn get_quota(&self, tenant_id: &str) -> SQLResult<TenantQuota, MultiTenantError>
+    {
+        let quota = self
+            .master_db
+            .query_row(SqlStatement::SelectQuota.as_str(), params![tenant_id], |row| {
+                Ok(TenantQuota {
+                    max_page_count: row.get(0)?,
+                    max_connections: row.get(1)?,
+                })
+            })
+            .optional()?;
+
+        Ok(quota.unwrap_or_default())
+    }
+
+    /// Sets the size and connection quota of a tenant and applies it to the open connection.
+    pub fn set_quota(&mut self, tenant_id: &str, quota: TenantQuota) -> SQLResult<(), MultiTenantError>
+    {
+        let connection = self.tenant_connection(tenant_id)?;
+
+        let tx = self.master_db.transaction()?;
+        tx.execute(
+            SqlStatement::UpsertQuota.as_str(),
+            params![tenant_id, quota.max_page_count, quota.max_connections],
+        )?;
+
+        Self::apply_page_quota(&connection.connection, quota.max_page_count)?;
+        tx.commit()?;
+
+        info!("Updated ({}) tenant quota to {:?}.", tenant_id, quota);
+        self.quotas.insert(tenant_id.to_string(), quota);
+
+        Ok(())
+    }
+
+    /// Limits the amount of rows `table` can hold in a tenant database, or lifts the limit with `None`.
+    ///
+    /// The limit is enforced by a trigger, so inserts past it fail with `QuotaExceeded`. The table must exist.
+    pub fn set_row_limit(&mut self, tenant_id: &str, table: &str, max_rows: Option<u64>) -> SQLResult<(), MultiTenantError>
+    {
+        let connection = self.tenant_connection(tenant_id)?;
+
+        let tx = self.master_db.transaction()?;
+
+        match max_rows {
+            Some(max_rows) => {
+                tx.execute(SqlStatement::UpsertRowLimit.as_str(), params![tenant_id, table, max_rows])?;
+                Self::install_row_limit(&connection.connection, table, max_rows)?;
+            }
+            None => {
+                tx.execute(SqlStatement::DeleteRowLimit.as_str(), params![tenant_id, table])?;
+                connection.connection.execute(
+                    &format!("DROP TRIGGER IF EXISTS {};", quote_identifier(&row_limit_trigger(table))),
+                    [],
+                )?;
+            }
+        }
+
+        tx.commit()?;
+
+        info!("Updated ({}) tenant row limit on {} to {:?}.", tenant_id, table, max_rows);
+
+        Ok(())
+    }
+
+    /// Reads the current usage of a tenant against each of its limits.
+    pub fn quota_usage(&mut self, tenant_id: &str) -> SQLResult<QuotaUsage, MultiTenantError>
+    {
+        let connection = self.tenant_connection(tenant_id)?;
+        let quota = self.get_quota(tenant_id)?;
+        let conn = &connection.connection;
+
+        let mut tables = Vec::new();
+
+        for (table, max_rows) in self.row_limits(tenant_id)? {
+            let rows = conn.query_row(&format!("SELECT COUNT(*) FROM {};", quote_identifier(&table)), [], |row| {
+                row.get(0)
+            })?;
+
+            tables.push(TableRowUsage { table, rows, max_rows });
+        }
+
+        Ok(QuotaUsage {
+            page_count: conn.pragma_query_value(None, "page_count", |row| row.get(0))?,
+            max_page_count: quota.max_page_count,
+            // One reference is held by the cache and one by `connection` above.
+            connections: Arc::strong_count(conn).saturating_sub(2),
+            max_connections: quota.max_connections,
+            tables,
+        })
+    }
+
+    /// Applies the stored quotas of a tenant to a freshly opened connection.
+    pub(crate) fn apply_quotas(&mut self, tenant_id: &str, connection: &TenantConnection)
+        -> SQLResult<(), MultiTenantError>
+    {
+        let quota = self.get_quota(tenant_id)?;
+
+        Self::apply_page_quota(&connection.connection, quota.max_page_count)?;
+
+        // Row limit triggers live in the tenant file, a read-only connection could not install them and has no inserts
+        // for them to stop.
+        if !connection.is_read_only() {
+            for (table, max_rows) in self.row_limits(tenant_id)? {
+                if let Err(err) = Self::install_row_limit(&connection.connection, &table, max_rows) {
+                    warn!("Failed to apply ({}) tenant row limit on {}: {}", tenant_id, table, err);
+                }
+            }
+        }
+
+        if quota == TenantQuota::default() {
+            self.quotas.remove(tenant_id);
+        } else {
+            self.quotas.insert(tenant_id.to_string(), quota);
+        }
+
+        Ok(())
+    }
+
+    /// Fails with `QuotaExceeded` if handing out `connection` puts the tenant over its connection limit.
+    pub(crate) fn check_connection_quota(
+        &self,
+        tenant_id: &str,
+        connection: &TenantConnection,
+    ) -> SQLResult<(), MultiTenantError>
+    {
+        let Some(max_connections) = self.quotas.get(tenant_id).and_then(|quota| quota.max_connections) else {
+            return Ok(());
+        };
+
+        // One reference is held by the cache and one by the connection about to be returned.
+        let outstanding = Arc::strong_count(&connection.connection).saturating_sub(2);
+
+        if outstanding >= max_connections {
+            warn!(
+                "Tenant ({}) has reached its limit of {} connections.",
+                tenant_id, max_connections
+            );
+            return Err(MultiTenantError::QuotaExceeded(format!(
+                "tenant '{}' is limited to {} connections",
+                tenant_id, max_connections
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn row_limits(&self, tenant_id: &str) -> SQLResult<Vec<(String, u64)>, MultiTenantError>
+    {
+        let mut statement = self.master_db.prepare(SqlStatement::SelectRowLimits.as_str())?;
+
+        let limits = statement
+            .query_map(params![tenant_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<SQLResult<Vec<_>>>()?;
+
+        Ok(limits)
+    }
+
+    fn apply_page_quota(conn: &Connection, max_page_count: Option<u64>) -> SQLResult<()>
+    {
+        conn.pragma_update(None, "max_page_count", max_page_count.unwrap_or(UNLIMITED_PAGE_COUNT))
+    }
+
+    /// Creates the trigger enforcing a row limit, unless the tenant file already has it with the same limit.
+    fn install_row_limit(conn: &Connection, table: &str, max_rows: u64) -> SQLResult<()>
+    {
+        let trigger = row_limit_trigger(table);
+        let message = format!("{}table '{}' is limited to {} rows", QUOTA_EXCEEDED_PREFIX, table, max_rows);
+
+        let create = format!(
+            "CREATE TRIGGER {trigger} BEFORE INSERT ON {table}
+            WHEN (SELECT COUNT(*) FROM {table}) >= {max_rows}
+            BEGIN SELECT RAISE(ABORT, {message}); END",
+            trigger = quote_identifier(&trigger),
+            table = quote_identifier(table),
+            max_rows = max_rows,
+            message = quote_literal(&message),
+        );
+
+        // SQLite keeps the statement a trigger was created with, so an unchanged limit matches it exactly.
+        let installed: Option<String> = conn
+            .query_row(
+                "SELECT sql FROM sqlite_master WHERE type = 'trigger' AND name = ?1;",
+                params![trigger],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        if installed.as_deref() == Some(create.as_str()) {
+            return Ok(());
+        }
+
+        conn.execute_batch(&format!(
+            "DROP TRIGGER IF EXISTS {};\n{};",
+            quote_identifier(&trigger),
+            create
+        ))
+    }
+}
+
+/// The unquoted name of the trigger that enforces the row limit of `table`.
+fn row_limit_trigger(table: &str) -> String
+{
+    format!("quota_rows_{}", table)
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use rusqlite::Connection;
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+use crate::observer::EvictionReason;
+use crate::telemetry;
+
+/// What a single `tick` did.
+#[derive(Debug, Default)]
+pub struct TickReport
+{
+    /// Tenants whose idle connection was closed.
+    pub reaped: Vec<String>,
+    /// Tenants whose idle connection failed to close cleanly. They are out of the cache either way.
+    pub failed: Vec<(String, MultiTenantError)>,
+    /// Tenants added to the cache by a running `warm_cache`.
+    pub warmed: Vec<String>,
+    /// Tenants whose idle connection the `CachePolicy::Ttl` policy expired.
+    pub expired: Vec<String>,
+}
+
+impl MultiTenantManager
+{
+    /// Runs the manager's periodic housekeeping. Call it on an interval from your own loop or timer.
+    ///
+    /// Closes cached connections idle for longer than `Configuration::idle_timeout`, after running `PRAGMA optimize`
+    /// and a WAL checkpoint on them. Connections that are borrowed, pinned or in-memory are left open.
+    ///
+    /// Connections opened by a running `warm_cache` are added to the cache, idle connections past the
+    /// `CachePolicy::Ttl` are closed, and access stats are flushed once `Configuration::stats_flush_interval` has
+    /// passed.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "manager.tick", skip_all))]
+    pub fn tick(&mut self) -> TickReport
+    {
+        let mut report = TickReport {
+            warmed: self.drain_warmup(),
+            ..Default::default()
+        };
+
+        self.flush_stats_if_due();
+        report.expired = self.evict_expired();
+        self.prune_detached();
+
+        self.last_access
+            .retain(|tenant_id, _| self.cache.contains(tenant_id) || self.readonly_cache.contains(tenant_id));
+
+        let Some(idle_timeout) = self.idle_timeout else {
+            return report;
+        };
+
+        let idle: Vec<String> = self
+            .cache
+            .eviction_order()
+            .into_iter()
+            .filter(|tenant_id| {
+                self.last_access
+                    .get(tenant_id)
+                    .is_some_and(|last_access| last_access.elapsed() > idle_timeout)
+            })
+            .filter(|tenant_id| {
+                self.cache
+                    .peek(tenant_id)
+                    .is_some_and(|connection| Arc::strong_count(&connection.connection) == 1 && !connection.is_in_memory())
+            })
+            .collect();
+
+        for tenant_id in idle {
+            let Some(connection) = self.cache.remove(&tenant_id) else {
+                continue;
+            };
+
+            self.last_access.remove(&tenant_id);
+            self.record_eviction(&tenant_id, EvictionReason::Reaped);
+            telemetry::tenants_closed(1);
+
+            let connection = match Arc::try_unwrap(connection.connection) {
+                Ok(connection) => connection,
+                Err(_) => {
+                    report.failed.push((
+                        tenant_id.clone(),
+                        MultiTenantError::DatabaseError(format!("Failed to unwrap Arc for {}", tenant_id)),
+                    ));
+                    continue;
+                }
+            };
+
+            match Self::close_idle(connection) {
+                Ok(()) => {
+                    info!("Reaped ({}) tenant after {:?} idle.", tenant_id, idle_timeout);
+                    report.reaped.push(tenant_id);
+                }
+                Err(err) => {
+                    warn!("Failed to cleanly close idle ({}) tenant: {}", tenant_id, err);
+                    report.failed.push((tenant_id, err));
+                }
+            }
+        }
+
+        self.reap_idle_readonly(idle_timeout, &mut report);
+
+        if !report.reaped.is_empty() || !report.failed.is_empty() {
+            self.report_open_connections();
+        }
+
+        debug!("Tick reaped {} idle tenants.", report.reaped.len());
+
+        report
+    }
+
+    /// Closes read-only connections idle for longer than `idle_timeout`. They can not be optimized or checkpointed,
+    /// so they are closed as they are.
+    fn reap_idle_readonly(&mut self, idle_timeout: Duration, report: &mut TickReport)
+    {
+        let idle: Vec<String> = self
+            .readonly_cache
+            .eviction_order()
+            .into_iter()
+            .filter(|tenant_id| {
+                self.last_access
+                    .get(tenant_id)
+                    .is_some_and(|last_access| last_access.elapsed() > idle_timeout)
+            })
+            .filter(|tenant_id| {
+                self.readonly_cache
+                    .peek(tenant_id)
+                    .is_some_and(|connection| Arc::strong_count(&connection.connection) == 1)
+            })
+            .collect();
+
+        for tenant_id in idle {
+            let Some(connection) = self.readonly_cache.remove(&tenant_id) else {
+                continue;
+            };
+
+            if !self.cache.contains(&tenant_id) {
+                self.last_access.remove(&tenant_id);
+            }
+
+            self.record_eviction(&tenant_id, EvictionReason::Reaped);
+            telemetry::tenants_closed(1);
+
+            let connection = match Arc::try_unwrap(connection.connection) {
+                Ok(connection) => connection,
+                Err(_) => {
+                    report.failed.push((
+                        tenant_id.clone(),
+                        MultiTenantError::DatabaseError(format!("Failed to unwrap Arc for {}", tenant_id)),
+                    ));
+                    continue;
+                }
+            };
+
+            match connection.close() {
+                Ok(()) => {
+                    info!("Reaped ({}) read-only tenant after {:?} idle.", tenant_id, idle_timeout);
+
+                    if !report.reaped.contains(&tenant_id) {
+                        report.reaped.push(tenant_id);
+                    }
+                }
+                Err((_, err)) => {
+                    warn!("Failed to cleanly close idle read-only ({}) tenant: {}", tenant_id, err);
+                    report.failed.push((tenant_id, err.into()));
+                }
+            }
+        }
+    }
+
+    /// Optimizes and checkpoints a connection before closing it.
+    pub(crate) fn close_idle(connection: Connection) -> SQLResult<(), MultiTenantError>
+    {
+        connection.execute_batch("PRAGMA optimize;")?;
+        connection.query_row("PRAGMA wal_checkpoint(TRUNCATE);", [], |_| Ok(()))?;
+
+        connection.close().map_err(|(_, err)| err)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::{info, warn};
+use rusqlite::params;
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::health::database_files;
+use crate::manager::MultiTenantManager;
+use crate::observer;
+use crate::statements::SqlStatement;
+use crate::tenant::TenantRecord;
+use crate::tenant_id::TenantId;
+
+/// Extensions of the files in the storage dir that `reconcile` treats as tenant databases.
+const DATABASE_EXTENSIONS: [&str; 3] = ["sqlite", "sqlite3", "db"];
+
+/// What `reconcile` does with master rows whose tenant file is missing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum MissingFileAction
+{
+    /// Only list them in the report.
+    #[default]
+    Report,
+    /// Delete their master rows, as `remove_tenant` would.
+    Unregister,
+}
+
+/// What `reconcile` does with database files in the storage dir that have no master row.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum OrphanedFileAction
+{
+    /// Only list them in the report.
+    #[default]
+    Report,
+    /// Register them as tenants, using the file name without its extension as the tenant id. Files whose name is not
+    /// a valid `TenantId` are reported as failed.
+    Register,
+    /// Move them, along with their journal files, into the given directory. Files whose name is already taken there
+    /// are reported as failed.
+    Quarantine(PathBuf),
+    /// Delete them along with their journal files.
+    Delete,
+}
+
+/// How `reconcile` brings the master database and the storage dir back in line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReconcilePolicy
+{
+    pub missing: MissingFileAction,
+    pub orphaned: OrphanedFileAction,
+    /// Fills in the report with what would be done, without touching the master database or any file.
+    pub dry_run: bool,
+}
+
+/// What a `reconcile` found, and what it did about it.
+#[derive(Debug, Default)]
+pub struct ReconcileReport
+{
+    /// Tenants registered in the storage dir whose file does not exist.
+    pub missing: Vec<String>,
+    /// Database files in the storage dir without a master row.
+    pub orphaned: Vec<PathBuf>,
+    /// Tenants whose master rows were deleted.
+    pub unregistered: Vec<String>,
+    /// Orphaned files registered as tenants.
+    pub registered: Vec<(String, PathBuf)>,
+    /// Orphaned files moved into quarantine, with where they were moved to.
+    pub quarantined: Vec<(PathBuf, PathBuf)>,
+    /// Orphaned files deleted.
+    pub deleted: Vec<PathBuf>,
+    /// Missing tenants or orphaned files the policy could not be applied to.
+    pub failed: Vec<(String, MultiTenantError)>,
+    /// `true` if nothing was actually changed.
+    pub dry_run: bool,
+}
+
+impl MultiTenantManager
+{
+    /// Compares the tenants registered in `storage_dir` with the database files in it, and applies `policy` to the
+    /// differences.
+    ///
+    /// Only tenants whose file is directly inside `storage_dir` are considered missing, and only files with a
+    /// `.sqlite`, `.sqlite3` or `.db` extension are considered orphaned.
+    pub fn reconcile<P: AsRef<Path>>(
+        &mut self,
+        storage_dir: P,
+        policy: ReconcilePolicy,
+    ) -> SQLResult<ReconcileReport, MultiTenantError>
+    {
+        let storage_dir = storage_dir.as_ref();
+
+        let canonical_dir = storage_dir.canonicalize().map_err(|err| {
+            MultiTenantError::DatabaseError(format!("Failed to read storage dir {}: {}", storage_dir.display(), err))
+        })?;
+
+        let tenants: Vec<TenantRecord> = self
+            .list_tenants()?
+            .into_iter()
+            .filter(|tenant| tenant.path.is_some())
+            .collect();
+
+        let mut report = ReconcileReport {
+            missing: tenants
+                .iter()
+                .filter(|tenant| {
+                    tenant
+                        .path
+                        .as_deref()
+                        .is_some_and(|path| Self::is_missing_from(path, &canonical_dir))
+                })
+                .map(|tenant| tenant.tenant_id.clone())
+                .collect(),
+            orphaned: self
+                .orphaned_files(storage_dir, &tenants)?
+                .into_iter()
+                .filter(|path| Self::is_database_file(path))
+                .collect(),
+            dry_run: policy.dry_run,
+            ..Default::default()
+        };
+
+        if policy.missing == MissingFileAction::Unregister {
+            for tenant_id in report.missing.clone() {
+                match self.unregister_missing(&tenant_id, policy.dry_run) {
+                    Ok(()) => report.unregistered.push(tenant_id),
+                    Err(err) => report.failed.push((tenant_id, err)),
+                }
+            }
+        }
+
+        for path in report.orphaned.clone() {
+            let outcome = match &policy.orphaned {
+                OrphanedFileAction::Report => continue,
+                OrphanedFileAction::Register => self
+                    .register_orphan(&path, policy.dry_run)
+                    .map(|tenant_id| report.registered.push((tenant_id, path.clone()))),
+                OrphanedFileAction::Quarantine(quarantine_dir) => {
+                    Self::quarantine_orphan(&path, quarantine_dir, policy.dry_run)
+                        .map(|moved_to| report.quarantined.push((path.clone(), moved_to)))
+                }
+                OrphanedFileAction::Delete => {
+                    Self::delete_orphan(&path, policy.dry_run).map(|()| report.deleted.push(path.clone()))
+                }
+            };
+
+            if let Err(err) = outcome {
+                report.failed.push((path.display().to_string(), err));
+            }
+        }
+
+        info!(
+            "Reconciled {}{} missing tenants and {} orphaned files ({} failed).",
+            if policy.dry_run { "(dry run) " } else { "" },
+            report.missing.len(),
+            report.orphaned.len(),
+            report.failed.len()
+        );
+
+        Ok(report)
+    }
+
+    /// Returns `true` if `path` belongs in `storage_dir` and does not exist.
+    fn is_missing_from(path: &Path, storage_dir: &Path) -> bool
+    {
+        let in_storage_dir = path
+            .parent()
+            .and_then(|parent| parent.canonicalize().ok())
+            .is_some_and(|parent| parent == storage_dir);
+
+        in_storage_dir && !path.exists()
+    }
+
+    fn is_database_file(path: &Path) -> bool
+    {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| DATABASE_EXTENSIONS.contains(&extension))
+    }
+
+    /// Deletes the master rows of a tenant whose file is gone, dropping its cached connection.
+    fn unregister_missing(&mut self, tenant_id: &str, dry_run: bool) -> SQLResult<(), MultiTenantError>
+    {
+        if dry_run {
+            return Ok(());
+        }
+
+        observer::veto(&self.observers, |observer| observer.before_remove(tenant_id))?;
+
+        if let Some(connection) = self.cache.remove(tenant_id) {
+            if Arc::strong_count(&connection.connection) > 1 {
+                self.detached
+                    .push((tenant_id.to_string(), Arc::downgrade(&connection.connection)));
+            }
+        }
+
+        self.unregister_tenant(tenant_id)?;
+        warn!("Unregistered ({}) tenant, its file is missing.", tenant_id);
+
+        Ok(())
+    }
+
+    /// Registers an orphaned file under its file stem. Returns the new tenant id.
+    fn register_orphan(&mut self, path: &Path, dry_run: bool) -> SQLResult<String, MultiTenantError>
+    {
+        let tenant_id = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| MultiTenantError::DatabaseError(format!("{} has no usable file name", path.display())))?
+            .to_string();
+
+        TenantId::validate(&tenant_id)?;
+
+        let taken: bool =
+            self.master_db
+                .query_row(SqlStatement::SelectTenantExists.as_str(), params![tenant_id], |row| {
+                    row.get(0)
+                })?;
+
+        if taken {
+            return Err(MultiTenantError::TenantAlreadyExists(tenant_id));
+        }
+
+        if !dry_run {
+            observer::veto(&self.observers, |observer| observer.before_add(&tenant_id, Some(path)))?;
+
+            self.master_db.execute(
+                SqlStatement::InsertAddTenant.as_str(),
+                params![tenant_id, path.to_str(), true],
+            )?;
+            info!("Registered orphaned {} as ({}) tenant.", path.display(), tenant_id);
+
+            observer::notify(&self.observers, "add", &tenant_id, |observer| {
+                observer.on_added(&tenant_id, Some(path))
+            });
+        }
+
+        Ok(tenant_id)
+    }
+
+    /// Moves an orphaned file and its journal files into `quarantine_dir`. Returns where the file was moved to.
+    fn quarantine_orphan(path: &Path, quarantine_dir: &Path, dry_run: bool) -> SQLResult<PathBuf, MultiTenantError>
+    {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| MultiTenantError::DatabaseError(format!("{} has no file name", path.display())))?;
+        let moved_to = quarantine_dir.join(file_name);
+
+        // A file quarantined by an earlier run is never replaced, nor mixed with the journal files of this one.
+        if let Some(taken) = database_files(&moved_to).into_iter().find(|file| file.exists()) {
+            return Err(MultiTenantError::DatabaseError(format!(
+                "Failed to quarantine {}, {} already exists",
+                path.display(),
+                taken.display()
+            )));
+        }
+
+        if dry_run {
+            return Ok(moved_to);
+        }
+
+        fs::create_dir_all(quarantine_dir).map_err(|err| {
+            MultiTenantError::DatabaseError(format!("Failed to create {}: {}", quarantine_dir.display(), err))
+        })?;
+
+        for (from, to) in database_files(path).into_iter().zip(database_files(&moved_to)) {
+            if from.exists() {
+                fs::rename(&from, &to)
+                    .map_err(|err| MultiTenantError::DatabaseError(format!("Failed to move {}: {}", from.display(), err)))?;
+            }
+        }
+
+        warn!("Quarantined orphaned {} to {}.", path.display(), moved_to.display());
+
+        Ok(moved_to)
+    }
+
+    /// Deletes an orphaned file and its journal files.
+    fn delete_orphan(path: &Path, dry_run: bool) -> SQLResult<(), MultiTenantError>
+    {
+        if dry_run {
+            return Ok(());
+        }
+
+        for file in database_files(path) {
+            if file.exists() {
+                fs::remove_file(&file).map_err(|err| {
+                    MultiTenantError::DatabaseError(format!("Failed to delete {}: {}", file.display(), err))
+                })?;
+            }
+        }
+
+        warn!("Deleted orphaned {}.", path.display());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fmt, fs};
+
+use log::{error, info, warn};
+use rusqlite::{params, Connection, ErrorCode};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::health::SIDECAR_SUFFIXES;
+use crate::manager::MultiTenantManager;
+use crate::observer;
+use crate::tenant::TenantConnection;
+
+/// Runs on a database created by `MissingFilePolicy::Recreate`, such as to create the tenant's schema again.
+pub type TenantInitHook = Arc<dyn Fn(&str, &Connection) -> SQLResult<(), MultiTenantError> + Send + Sync>;
+
+/// What the manager does when the file of a registered tenant does not exist as it is opened.
+#[derive(Clone, Default)]
+pub enum MissingFilePolicy
+{
+    /// Fails with `TenantFileMissing`, leaving the tenant registered.
+    #[default]
+    Error,
+    /// Creates an empty database in place of the file, then runs `init` on it.
+    Recreate
+    {
+        init: Option<TenantInitHook>
+    },
+    /// Copies the latest backup of the tenant in the given directory in place of the file, see
+    /// `MultiTenantManager::backup_tenant`. Fails with `TenantFileMissing` if the tenant has no backup.
+    RestoreLatestBackup(PathBuf),
+}
+
+impl fmt::Debug for MissingFilePolicy
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self {
+            MissingFilePolicy::Error => write!(f, "Error"),
+            MissingFilePolicy::Recreate { init } => f
+                .debug_struct("Recreate")
+                .field("init", &init.as_ref().map(|_| "TenantInitHook"))
+                .finish(),
+            MissingFilePolicy::RestoreLatestBackup(backup_dir) => {
+                f.debug_tuple("RestoreLatestBackup").field(backup_dir).finish()
+            }
+        }
+    }
+}
+
+/// Finds the newest `{tenant_id}-{unix millis}.sqlite` backup in `backup_dir`.
+pub(crate) fn latest_backup(tenant_id: &str, backup_dir: &Path) -> Option<PathBuf>
+{
+    let prefix = format!("{}-", tenant_id);
+
+    fs::read_dir(backup_dir)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let millis: u64 = entry
+                .file_name()
+                .to_str()?
+                .strip_prefix(&prefix)?
+                .strip_suffix(".sqlite")?
+                .parse()
+                .ok()?;
+
+            Some((millis, entry.path()))
+        })
+        .max_by_key(|(millis, _)| *millis)
+        .map(|(_, path)| path)
+}
+
+/// Deletes journal files left next to a missing database, so SQLite does not replay them into its replacement.
+fn remove_sidecars(path: &Path) -> SQLResult<(), MultiTenantError>
+{
+    for suffix in SIDECAR_SUFFIXES {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(suffix);
+        let sidecar = PathBuf::from(sidecar);
+
+        if sidecar.exists() {
+            fs::remove_file(&sidecar).map_err(|err| {
+                MultiTenantError::DatabaseError(format!("Failed to delete {}: {}", sidecar.display(), err))
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+impl MultiTenantManager
+{
+    /// Copies a tenant into `backup_dir` as `{tenant_id}-{unix millis}.sqlite`, the name
+    /// `MissingFilePolicy::RestoreLatestBackup` looks for. Returns the path of the backup.
+    pub fn backup_tenant<P: AsRef<Path>>(&mut self, tenant_id: &str, backup_dir: P) -> SQLResult<PathBuf, MultiTenantError>
+    {
+        let backup_dir = backup_dir.as_ref();
+        let connection = self.tenant_connection(tenant_id)?;
+
+        fs::create_dir_all(backup_dir)
+            .map_err(|err| MultiTenantError::DatabaseError(format!("Failed to create {}: {}", backup_dir.display(), err)))?;
+
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let destination = backup_dir.join(format!("{}-{}.sqlite", tenant_id, millis));
+
+        if destination.exists() {
+            return Err(MultiTenantError::DatabaseError(format!(
+                "Backup {} already exists",
+                destination.display()
+            )));
+        }
+
+        connection
+            .connection
+            .execute("VACUUM INTO ?1;", params![destination.to_str()])?;
+
+        info!("Backed up ({}) tenant to {}.", tenant_id, destination.display());
+
+        Ok(destination)
+    }
+
+    /// Opens the file of a registered tenant with `open`, applying the `MissingFilePolicy` if it does not exist.
+    ///
+    /// The file is only looked for once opening it fails with `SQLITE_CANTOPEN`, so a file deleted right before it is
+    /// opened still goes through the policy.
+    pub(crate) fn open_tenant_file<F>(
+        &self,
+        tenant_id: &str,
+        path: &Path,
+        open: F,
+    ) -> SQLResult<TenantConnection, MultiTenantError>
+    where
+        F: Fn(&Path) -> SQLResult<TenantConnection>,
+    {
+        match open(path) {
+            Err(err) if err.sqlite_error_code() == Some(ErrorCode::CannotOpen) && !path.exists() => {
+                self.recover_tenant_file(tenant_id, path)?;
+                Ok(open(path)?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Applies the `MissingFilePolicy` to a registered tenant whose file does not exist. On success there is a file
+    /// at `path` to open.
+    pub(crate) fn recover_tenant_file(&self, tenant_id: &str, path: &Path) -> SQLResult<(), MultiTenantError>
+    {
+        let missing = || MultiTenantError::TenantFileMissing(format!("'{}' at {}", tenant_id, path.display()));
+
+        match &self.missing_file_policy {
+            MissingFilePolicy::Error => {
+                error!("File of ({}) tenant is missing: {}", tenant_id, path.display());
+                Err(missing())
+            }
+            MissingFilePolicy::Recreate { init } => {
+                remove_sidecars(path)?;
+
+                let connection = Connection::open(path)?;
+
+                if let Some(init) = init {
+                    init(tenant_id, &connection)?;
+                }
+
+                connection.close().map_err(|(_, err)| err)?;
+
+                warn!("Recreated the missing file of ({}) tenant at {}.", tenant_id, path.display());
+                observer::notify(&self.observers, "file recovery", tenant_id, |observer| {
+                    observer.on_file_recovered(tenant_id, path)
+                });
+
+                Ok(())
+            }
+            MissingFilePolicy::RestoreLatestBackup(backup_dir) => {
+                let Some(backup) = latest_backup(tenant_id, backup_dir) else {
+                    error!(
+                        "File of ({}) tenant is missing and it has no backup in {}.",
+                        tenant_id,
+                        backup_dir.display()
+                    );
+                    return Err(missing());
+                };
+
+                remove_sidecars(path)?;
+
+                fs::copy(&backup, path).map_err(|err| {
+                    MultiTenantError::DatabaseError(format!(
+                        "Failed to restore {} to {}: {}",
+                        backup.display(),
+                        path.display(),
+                        err
+                    ))
+                })?;
+
+                warn!(
+                    "Restored the missing file of ({}) tenant from {}.",
+                    tenant_id,
+                    backup.display()
+                );
+                observer::notify(&self.observers, "file recovery", tenant_id, |observer| {
+                    observer.on_file_recovered(tenant_id, path)
+                });
+
+                Ok(())
+            }
+        }
+    }
+}
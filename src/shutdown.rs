@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use log::{error, info, warn};
+use rusqlite::Connection;
+
+use crate::error::MultiTenantError;
+use crate::manager::MultiTenantManager;
+use crate::telemetry;
+use crate::tenant::TenantConnection;
+
+/// What `shutdown` did.
+#[derive(Debug, Default)]
+pub struct ShutdownReport
+{
+    /// Tenants whose connections were checkpointed and closed.
+    pub closed: Vec<String>,
+    /// Tenants that failed to close cleanly, including ones that were still borrowed.
+    pub failed: Vec<(String, MultiTenantError)>,
+    /// Errors flushing access stats to, or closing, the master database.
+    pub master_errors: Vec<MultiTenantError>,
+}
+
+impl ShutdownReport
+{
+    /// Returns `true` if every tenant and the master database closed without an error.
+    pub fn is_clean(&self) -> bool
+    {
+        self.failed.is_empty() && self.master_errors.is_empty()
+    }
+}
+
+impl MultiTenantManager
+{
+    /// Shuts the manager down, closing every tenant connection and then the master database.
+    ///
+    /// Flushes access stats, then runs `PRAGMA optimize` and `wal_checkpoint(TRUNCATE)` on every cached read-write
+    /// connection before closing it. Read-only connections are closed as they are.
+    ///
+    /// Borrowed connections are reported as failed right away, and close whenever their last borrow is dropped.
+    /// Waiting for them would never end, since a `TenantConnection` can not leave the thread calling `shutdown`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "manager.shutdown", skip_all))]
+    pub fn shutdown(mut self) -> ShutdownReport
+    {
+        let mut report = ShutdownReport::default();
+
+        // Connections still being warmed are dropped with their threads' channels.
+        self.warmups.clear();
+
+        let mut tenant_ids = self.cache.eviction_order();
+        tenant_ids.extend(self.readonly_cache.eviction_order());
+
+        match self.pinned_tenants() {
+            Ok(pinned) => tenant_ids.extend(pinned.into_iter().map(|tenant| tenant.tenant_id)),
+            Err(err) => warn!("Failed to list pinned tenants while shutting down: {}", err),
+        }
+
+        tenant_ids.sort();
+        tenant_ids.dedup();
+
+        if let Err(err) = self.flush_stats() {
+            error!("Failed to flush tenant access stats while shutting down: {}", err);
+            report.master_errors.push(err);
+        }
+
+        self.pending_access.clear();
+
+        for tenant_id in tenant_ids {
+            let connections: Vec<TenantConnection> = [self.cache.remove(&tenant_id), self.readonly_cache.remove(&tenant_id)]
+                .into_iter()
+                .flatten()
+                .collect();
+
+            if connections.is_empty() {
+                continue;
+            }
+
+            let mut failed = false;
+
+            for connection in connections {
+                telemetry::tenants_closed(1);
+
+                let read_only = connection.is_read_only();
+
+                let result = match Arc::try_unwrap(connection.connection) {
+                    Ok(connection) if read_only => connection.close().map_err(|(_, err)| err.into()),
+                    Ok(connection) => Self::close_idle(connection),
+                    Err(_) => Err(MultiTenantError::DatabaseError(format!(
+                        "Connection for {} is still borrowed",
+                        tenant_id
+                    ))),
+                };
+
+                match result {
+                    Ok(()) => {}
+                    Err(err) => {
+                        warn!("Failed to cleanly close ({}) tenant while shutting down: {}", tenant_id, err);
+                        report.failed.push((tenant_id.clone(), err));
+                        failed = true;
+                    }
+                }
+            }
+
+            if !failed {
+                report.closed.push(tenant_id);
+            }
+        }
+
+        for (tenant_id, connection) in std::mem::take(&mut self.detached) {
+            if connection.strong_count() > 0 {
+                report.failed.push((
+                    tenant_id.clone(),
+                    MultiTenantError::DatabaseError(format!("Evicted connection for {} is still borrowed", tenant_id)),
+                ));
+            }
+        }
+
+        self.memory_anchors.clear();
+        self.last_access.clear();
+        self.report_open_connections();
+
+        // Swaps in a throwaway master so the real one can be closed, leaving nothing for `Drop` to flush.
+        let master_db = match Connection::open_in_memory() {
+            Ok(placeholder) => Some(std::mem::replace(&mut self.master_db, placeholder)),
+            Err(err) => {
+                report.master_errors.push(err.into());
+                None
+            }
+        };
+
+        if let Some(Err((_, err))) = master_db.map(Connection::close) {
+            error!("Failed to close the master database: {}", err);
+            report.master_errors.push(err.into());
+        }
+
+        info!(
+            "Shut down: {} tenants closed, {} failed.",
+            report.closed.len(),
+            report.failed.len()
+        );
+
+        report
+    }
+}
@@ -12,18 +12,116 @@ pub(crate) struct MasterDbTable
     created_at: String,
 }
 
+/// Quotes an identifier (table, schema, trigger...) so it can be used in generated SQL.
+pub fn quote_identifier(identifier: &str) -> String
+{
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Quotes a string literal for SQL that can not take bound parameters, such as views and triggers.
+pub(crate) fn quote_literal(literal: &str) -> String
+{
+    format!("'{}'", literal.replace('\'', "''"))
+}
+
 /// SQL statements used in the tenant manager.
 pub(crate) enum SqlStatement
 {
     CreateMasterDb,
+    CreateTenantQuotas,
+    CreateTenantRowLimits,
     InsertAddTenant,
     DeleteRemoveTenant,
     SelectTenant,
     SelectTenantCounts,
+    SelectTenantExists,
+    SelectTenantList,
+    SelectTenantRecord,
+    UpdateRenameTenant,
+    SelectQuota,
+    UpsertQuota,
+    UpdateRenameQuota,
+    DeleteQuota,
+    SelectRowLimits,
+    UpsertRowLimit,
+    DeleteRowLimit,
+    UpdateRenameRowLimits,
+    DeleteRowLimits,
+    AlterAddLastAccessedAt,
+    AlterAddPinned,
+    AlterAddAccessCount,
+    AlterAddOpenCount,
+    UpdateTenantStats,
+    SelectTenantStats,
+    SelectInactiveTenants,
+    UpdatePinned,
+    SelectPinnedTenants,
+    SelectRecentlyUsedTenants,
+    AlterAddAccessMode,
+    UpdateAccessMode,
+    SelectAccessMode,
+    SelectReadOnlyTenants,
+    AlterAddLastMaintainedAt,
+    SelectMaintenanceCandidates,
+    UpdateLastMaintained,
+    SelectLastMaintained,
+    CreateTenantUsage,
+    UpsertUsage,
+    SelectUsageSince,
+    UpdateRenameUsage,
+    DeleteUsage,
 }
 
 impl SqlStatement
 {
+    /// Updates for master tables (other than `tenants`) keyed on `tenant_id`, bound as `(new_id, old_id)`.
+    ///
+    /// New tenant keyed tables must be listed here so `rename_tenant` keeps them in sync.
+    pub(crate) fn tenant_renames() -> &'static [SqlStatement]
+    {
+        &[
+            SqlStatement::UpdateRenameQuota,
+            SqlStatement::UpdateRenameRowLimits,
+            SqlStatement::UpdateRenameUsage,
+        ]
+    }
+
+    /// Deletes for master tables (other than `tenants`) keyed on `tenant_id`, bound as `(tenant_id)`.
+    pub(crate) fn tenant_deletes() -> &'static [SqlStatement]
+    {
+        &[
+            SqlStatement::DeleteQuota,
+            SqlStatement::DeleteRowLimits,
+            SqlStatement::DeleteUsage,
+        ]
+    }
+
+    /// Every table the master database needs, created in order by `init_master_db`.
+    pub(crate) fn schema() -> &'static [SqlStatement]
+    {
+        &[
+            SqlStatement::CreateMasterDb,
+            SqlStatement::CreateTenantQuotas,
+            SqlStatement::CreateTenantRowLimits,
+            SqlStatement::CreateTenantUsage,
+        ]
+    }
+
+    /// Columns added to master tables after they were first released, as `(table, column, statement)`.
+    ///
+    /// `init_master_db` runs the statement on master databases created before the column existed.
+    pub(crate) fn column_upgrades() -> &'static [(&'static str, &'static str, SqlStatement)]
+    {
+        &[
+            ("tenants", "last_accessed_at", SqlStatement::AlterAddLastAccessedAt),
+            ("tenants", "pinned", SqlStatement::AlterAddPinned),
+            ("tenants", "access_count", SqlStatement::AlterAddAccessCount),
+            ("tenants", "open_count", SqlStatement::AlterAddOpenCount),
+            ("tenants", "access_mode", SqlStatement::AlterAddAccessMode),
+            ("tenants", "last_maintained_at", SqlStatement::AlterAddLastMaintainedAt),
+        ]
+    }
+
     pub(crate) fn as_str(&self) -> &'static str
     {
         match self {
@@ -37,12 +135,128 @@ impl SqlStatement
                     created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
                 );"
             }
+            SqlStatement::CreateTenantQuotas => {
+                "
+                CREATE TABLE IF NOT EXISTS tenant_quotas (
+                    tenant_id TEXT PRIMARY KEY NOT NULL,
+                    max_page_count INTEGER,
+                    max_connections INTEGER
+                );"
+            }
+            SqlStatement::CreateTenantRowLimits => {
+                "
+                CREATE TABLE IF NOT EXISTS tenant_row_limits (
+                    tenant_id TEXT NOT NULL,
+                    table_name TEXT NOT NULL,
+                    max_rows INTEGER NOT NULL,
+                    PRIMARY KEY (tenant_id, table_name)
+                );"
+            }
             SqlStatement::InsertAddTenant => {
                 "INSERT INTO tenants (tenant_id, tenant_path, tenant_has_path) VALUES (?1, ?2, ?3);"
             }
-            SqlStatement::DeleteRemoveTenant => "DELETE FROM tenants WHERE id = ?1;",
+            SqlStatement::DeleteRemoveTenant => "DELETE FROM tenants WHERE tenant_id = ?1;",
             SqlStatement::SelectTenant => "SELECT tenant_path, tenant_has_path FROM tenants WHERE tenant_id = ?1;",
             SqlStatement::SelectTenantCounts => "SELECT COUNT(*) FROM tenants;",
+            SqlStatement::SelectTenantExists => "SELECT EXISTS(SELECT 1 FROM tenants WHERE tenant_id = ?1);",
+            SqlStatement::SelectTenantList => {
+                "SELECT tenant_id, tenant_path, tenant_has_path, created_at FROM tenants GROUP BY tenant_id ORDER BY \
+                 MIN(id);"
+            }
+            SqlStatement::SelectTenantRecord => {
+                "SELECT tenant_id, tenant_path, tenant_has_path, created_at FROM tenants WHERE tenant_id = ?1 ORDER BY id \
+                 LIMIT 1;"
+            }
+            SqlStatement::UpdateRenameTenant => "UPDATE tenants SET tenant_id = ?1 WHERE tenant_id = ?2;",
+            SqlStatement::SelectQuota => "SELECT max_page_count, max_connections FROM tenant_quotas WHERE tenant_id = ?1;",
+            SqlStatement::UpsertQuota => {
+                "INSERT INTO tenant_quotas (tenant_id, max_page_count, max_connections) VALUES (?1, ?2, ?3) ON CONFLICT \
+                 (tenant_id) DO UPDATE SET max_page_count = excluded.max_page_count, max_connections = \
+                 excluded.max_connections;"
+            }
+            SqlStatement::UpdateRenameQuota => "UPDATE tenant_quotas SET tenant_id = ?1 WHERE tenant_id = ?2;",
+            SqlStatement::DeleteQuota => "DELETE FROM tenant_quotas WHERE tenant_id = ?1;",
+            SqlStatement::SelectRowLimits => {
+                "SELECT table_name, max_rows FROM tenant_row_limits WHERE tenant_id = ?1 ORDER BY table_name;"
+            }
+            SqlStatement::UpsertRowLimit => {
+                "INSERT INTO tenant_row_limits (tenant_id, table_name, max_rows) VALUES (?1, ?2, ?3) ON CONFLICT \
+                 (tenant_id, table_name) DO UPDATE SET max_rows = excluded.max_rows;"
+            }
+            SqlStatement::DeleteRowLimit => "DELETE FROM tenant_row_limits WHERE tenant_id = ?1 AND table_name = ?2;",
+            SqlStatement::UpdateRenameRowLimits => "UPDATE tenant_row_limits SET tenant_id = ?1 WHERE tenant_id = ?2;",
+            SqlStatement::DeleteRowLimits => "DELETE FROM tenant_row_limits WHERE tenant_id = ?1;",
+            SqlStatement::AlterAddLastAccessedAt => "ALTER TABLE tenants ADD COLUMN last_accessed_at TEXT;",
+            SqlStatement::AlterAddPinned => "ALTER TABLE tenants ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;",
+            SqlStatement::AlterAddAccessCount => "ALTER TABLE tenants ADD COLUMN access_count INTEGER NOT NULL DEFAULT 0;",
+            SqlStatement::AlterAddOpenCount => "ALTER TABLE tenants ADD COLUMN open_count INTEGER NOT NULL DEFAULT 0;",
+            SqlStatement::UpdateTenantStats => {
+                "UPDATE tenants SET access_count = access_count + ?2, open_count = open_count + ?3, last_accessed_at = \
+                 COALESCE(MAX(COALESCE(last_accessed_at, ''), strftime('%Y-%m-%d %H:%M:%f', ?4 / 1000.0, 'unixepoch')), \
+                 last_accessed_at) WHERE tenant_id = ?1;"
+            }
+            SqlStatement::SelectTenantStats => {
+                "SELECT tenant_id, last_accessed_at, access_count, open_count FROM tenants WHERE tenant_id = ?1 ORDER BY id \
+                 LIMIT 1;"
+            }
+            SqlStatement::SelectInactiveTenants => {
+                "SELECT tenant_id, last_accessed_at, access_count, open_count FROM tenants WHERE last_accessed_at IS NULL \
+                 OR last_accessed_at < strftime('%Y-%m-%d %H:%M:%f', ?1 / 1000.0, 'unixepoch') GROUP BY tenant_id ORDER BY \
+                 last_accessed_at;"
+            }
+            SqlStatement::UpdatePinned => "UPDATE tenants SET pinned = ?2 WHERE tenant_id = ?1;",
+            SqlStatement::SelectPinnedTenants => {
+                "SELECT tenant_id, tenant_path, tenant_has_path, created_at FROM tenants WHERE pinned = 1 GROUP BY \
+                 tenant_id ORDER BY MIN(id);"
+            }
+            SqlStatement::SelectRecentlyUsedTenants => {
+                "SELECT tenant_id, tenant_path, tenant_has_path, created_at FROM tenants WHERE last_accessed_at IS NOT NULL \
+                 GROUP BY tenant_id ORDER BY MAX(last_accessed_at) DESC LIMIT ?1;"
+            }
+            SqlStatement::AlterAddAccessMode => {
+                "ALTER TABLE tenants ADD COLUMN access_mode TEXT NOT NULL DEFAULT 'read_write';"
+            }
+            SqlStatement::UpdateAccessMode => "UPDATE tenants SET access_mode = ?2 WHERE tenant_id = ?1;",
+            SqlStatement::SelectAccessMode => "SELECT access_mode FROM tenants WHERE tenant_id = ?1 ORDER BY id LIMIT 1;",
+            SqlStatement::SelectReadOnlyTenants => {
+                "SELECT DISTINCT tenant_id FROM tenants WHERE access_mode != 'read_write';"
+            }
+            SqlStatement::AlterAddLastMaintainedAt => "ALTER TABLE tenants ADD COLUMN last_maintained_at TEXT;",
+            SqlStatement::SelectMaintenanceCandidates => {
+                "SELECT tenant_id, tenant_path, tenant_has_path, created_at FROM tenants WHERE tenant_has_path = 1 GROUP BY \
+                 tenant_id HAVING ?1 IS NULL OR MAX(last_maintained_at) IS NULL OR MAX(last_maintained_at) < \
+                 strftime('%Y-%m-%d %H:%M:%f', ?1 / 1000.0, 'unixepoch') ORDER BY MAX(last_maintained_at), MIN(id);"
+            }
+            SqlStatement::UpdateLastMaintained => {
+                "UPDATE tenants SET last_maintained_at = strftime('%Y-%m-%d %H:%M:%f', 'now') WHERE tenant_id = ?1;"
+            }
+            SqlStatement::CreateTenantUsage => {
+                "
+                CREATE TABLE IF NOT EXISTS tenant_usage (
+                    tenant_id TEXT PRIMARY KEY NOT NULL,
+                    page_count INTEGER NOT NULL,
+                    page_size INTEGER NOT NULL,
+                    freelist_count INTEGER NOT NULL,
+                    file_size INTEGER NOT NULL,
+                    measured_at TEXT NOT NULL
+                );"
+            }
+            SqlStatement::UpsertUsage => {
+                "INSERT INTO tenant_usage (tenant_id, page_count, page_size, freelist_count, file_size, measured_at) VALUES \
+                 (?1, ?2, ?3, ?4, ?5, strftime('%Y-%m-%d %H:%M:%f', 'now')) ON CONFLICT (tenant_id) DO UPDATE SET \
+                 page_count = excluded.page_count, page_size = excluded.page_size, freelist_count = \
+                 excluded.freelist_count, file_size = excluded.file_size, measured_at = excluded.measured_at RETURNING \
+                 tenant_id, page_count, page_size, freelist_count, file_size, measured_at;"
+            }
+            SqlStatement::SelectUsageSince => {
+                "SELECT tenant_id, page_count, page_size, freelist_count, file_size, measured_at FROM tenant_usage WHERE \
+                 measured_at >= strftime('%Y-%m-%d %H:%M:%f', ?1 / 1000.0, 'unixepoch');"
+            }
+            SqlStatement::UpdateRenameUsage => "UPDATE tenant_usage SET tenant_id = ?1 WHERE tenant_id = ?2;",
+            SqlStatement::DeleteUsage => "DELETE FROM tenant_usage WHERE tenant_id = ?1;",
+            SqlStatement::SelectLastMaintained => {
+                "SELECT last_maintained_at FROM tenants WHERE tenant_id = ?1 ORDER BY id LIMIT 1;"
+            }
         }
     }
 }
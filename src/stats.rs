@@ -0,0 +1,138 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use log::{debug, error};
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+use crate::statements::SqlStatement;
+use crate::telemetry;
+
+/// Access statistics of a tenant as recorded in the master database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TenantStats
+{
+    pub tenant_id: String,
+    /// UTC timestamp of the last `get_connection`, `None` if the tenant was never accessed.
+    pub last_accessed_at: Option<String>,
+    /// The amount of times `get_connection` returned this tenant.
+    pub access_count: u64,
+    /// The amount of times a connection to this tenant was opened.
+    pub open_count: u64,
+}
+
+/// Accesses recorded in memory since the last flush.
+#[derive(Debug, Default)]
+pub(crate) struct PendingAccess
+{
+    accesses: u64,
+    opens: u64,
+    /// Milliseconds since the unix epoch.
+    last_accessed_at: Option<u64>,
+}
+
+pub(crate) fn unix_millis(time: SystemTime) -> u64
+{
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl MultiTenantManager
+{
+    /// Gets the access statistics of a tenant, flushing any pending ones first.
+    pub fn tenant_stats(&mut self, tenant_id: &str) -> SQLResult<Option<TenantStats>, MultiTenantError>
+    {
+        self.flush_stats()?;
+
+        Ok(self
+            .master_db
+            .query_row(
+                SqlStatement::SelectTenantStats.as_str(),
+                params![tenant_id],
+                Self::tenant_stats_from_row,
+            )
+            .optional()?)
+    }
+
+    /// Lists tenants that have not been accessed since `since`, including tenants that were never accessed.
+    pub fn inactive_tenants(&mut self, since: SystemTime) -> SQLResult<Vec<TenantStats>, MultiTenantError>
+    {
+        self.flush_stats()?;
+
+        let mut statement = self.master_db.prepare(SqlStatement::SelectInactiveTenants.as_str())?;
+
+        let tenants = statement
+            .query_map(params![unix_millis(since)], Self::tenant_stats_from_row)?
+            .collect::<SQLResult<Vec<_>>>()?;
+
+        Ok(tenants)
+    }
+
+    /// Writes the access statistics recorded since the last flush to the master database in one transaction.
+    ///
+    /// This happens on its own every `Configuration::stats_flush_interval`, as well as from `tick`.
+    pub fn flush_stats(&mut self) -> SQLResult<(), MultiTenantError>
+    {
+        self.last_stats_flush = Instant::now();
+
+        if self.pending_access.is_empty() {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        let tx = self.master_db.transaction()?;
+
+        for (tenant_id, pending) in &self.pending_access {
+            tx.execute(
+                SqlStatement::UpdateTenantStats.as_str(),
+                params![tenant_id, pending.accesses, pending.opens, pending.last_accessed_at],
+            )?;
+        }
+
+        tx.commit()?;
+
+        telemetry::master_query("flush_stats", started.elapsed());
+        debug!("Flushed access stats of {} tenants.", self.pending_access.len());
+        self.pending_access.clear();
+
+        Ok(())
+    }
+
+    /// Records a `get_connection` of a tenant.
+    pub(crate) fn record_access(&mut self, tenant_id: &str)
+    {
+        let pending = self.pending_access.entry(tenant_id.to_string()).or_default();
+        pending.accesses += 1;
+        pending.last_accessed_at = Some(unix_millis(SystemTime::now()));
+
+        self.flush_stats_if_due();
+    }
+
+    /// Records a connection to a tenant being opened.
+    pub(crate) fn record_open(&mut self, tenant_id: &str)
+    {
+        self.pending_access.entry(tenant_id.to_string()).or_default().opens += 1;
+    }
+
+    pub(crate) fn flush_stats_if_due(&mut self)
+    {
+        if self.last_stats_flush.elapsed() < self.stats_flush_interval {
+            return;
+        }
+
+        if let Err(err) = self.flush_stats() {
+            error!("Failed to flush tenant access stats: {}", err);
+        }
+    }
+
+    fn tenant_stats_from_row(row: &Row) -> SQLResult<TenantStats>
+    {
+        Ok(TenantStats {
+            tenant_id: row.get(0)?,
+            last_accessed_at: row.get(1)?,
+            access_count: row.get(2)?,
+            open_count: row.get(3)?,
+        })
+    }
+}
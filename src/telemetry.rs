@@ -0,0 +1,109 @@
+//! Metrics reported through the `metrics` facade when the `metrics` feature is enabled.
+//!
+//! Install any `metrics` recorder (e.g. a Prometheus exporter) to collect them. Without the feature every function
+//! here compiles to nothing. Only misses and opens carry a `tenant` label, since they happen once per connection
+//! rather than once per request.
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+
+use std::time::Duration;
+
+/// Counter of connections served from the cache.
+#[cfg(feature = "metrics")]
+pub const CACHE_HITS: &str = "sqlite_tenant_cache_hits_total";
+/// Counter of connections not found in the cache, labelled by `tenant`.
+#[cfg(feature = "metrics")]
+pub const CACHE_MISSES: &str = "sqlite_tenant_cache_misses_total";
+/// Counter of connections removed from the cache, labelled by `reason` (`idle`, `expired` or `reaped`).
+#[cfg(feature = "metrics")]
+pub const CACHE_EVICTIONS: &str = "sqlite_tenant_cache_evictions_total";
+/// Counter of tenant connections opened, labelled by `tenant`.
+#[cfg(feature = "metrics")]
+pub const TENANTS_OPENED: &str = "sqlite_tenant_tenant_opens_total";
+/// Counter of tenant connections closed.
+#[cfg(feature = "metrics")]
+pub const TENANTS_CLOSED: &str = "sqlite_tenant_tenant_closes_total";
+/// Gauge of open tenant connections, cached and detached.
+#[cfg(feature = "metrics")]
+pub const OPEN_CONNECTIONS: &str = "sqlite_tenant_open_connections";
+/// Histogram of master database query latency in seconds, labelled by `operation`.
+#[cfg(feature = "metrics")]
+pub const MASTER_QUERY_SECONDS: &str = "sqlite_tenant_master_query_seconds";
+/// Counter of tenants added.
+#[cfg(feature = "metrics")]
+pub const TENANTS_ADDED: &str = "sqlite_tenant_tenants_added_total";
+/// Counter of tenants removed.
+#[cfg(feature = "metrics")]
+pub const TENANTS_REMOVED: &str = "sqlite_tenant_tenants_removed_total";
+
+/// Registers a description for every metric with the installed recorder.
+#[cfg(feature = "metrics")]
+pub fn describe_metrics()
+{
+    use metrics::{describe_counter, describe_gauge, describe_histogram, Unit};
+
+    describe_counter!(CACHE_HITS, "Tenant connections served from the cache.");
+    describe_counter!(CACHE_MISSES, "Tenant connections not found in the cache.");
+    describe_counter!(CACHE_EVICTIONS, "Tenant connections removed from the cache.");
+    describe_counter!(TENANTS_OPENED, "Tenant connections opened.");
+    describe_counter!(TENANTS_CLOSED, "Tenant connections closed.");
+    describe_gauge!(OPEN_CONNECTIONS, "Open tenant connections, cached and detached.");
+    describe_histogram!(MASTER_QUERY_SECONDS, Unit::Seconds, "Latency of master database queries.");
+    describe_counter!(TENANTS_ADDED, "Tenants added to the manager.");
+    describe_counter!(TENANTS_REMOVED, "Tenants removed from the manager.");
+}
+
+pub(crate) fn cache_hit()
+{
+    #[cfg(feature = "metrics")]
+    metrics::counter!(CACHE_HITS).increment(1);
+}
+
+pub(crate) fn cache_miss(tenant_id: &str)
+{
+    #[cfg(feature = "metrics")]
+    metrics::counter!(CACHE_MISSES, "tenant" => tenant_id.to_string()).increment(1);
+}
+
+pub(crate) fn eviction(reason: &'static str)
+{
+    #[cfg(feature = "metrics")]
+    metrics::counter!(CACHE_EVICTIONS, "reason" => reason).increment(1);
+}
+
+pub(crate) fn tenant_opened(tenant_id: &str)
+{
+    #[cfg(feature = "metrics")]
+    metrics::counter!(TENANTS_OPENED, "tenant" => tenant_id.to_string()).increment(1);
+}
+
+pub(crate) fn tenants_closed(count: usize)
+{
+    #[cfg(feature = "metrics")]
+    if count > 0 {
+        metrics::counter!(TENANTS_CLOSED).increment(count as u64);
+    }
+}
+
+pub(crate) fn open_connections(count: usize)
+{
+    #[cfg(feature = "metrics")]
+    metrics::gauge!(OPEN_CONNECTIONS).set(count as f64);
+}
+
+pub(crate) fn master_query(operation: &'static str, elapsed: Duration)
+{
+    #[cfg(feature = "metrics")]
+    metrics::histogram!(MASTER_QUERY_SECONDS, "operation" => operation).record(elapsed);
+}
+
+pub(crate) fn tenant_added()
+{
+    #[cfg(feature = "metrics")]
+    metrics::counter!(TENANTS_ADDED).increment(1);
+}
+
+pub(crate) fn tenant_removed()
+{
+    #[cfg(feature = "metrics")]
+    metrics::counter!(TENANTS_REMOVED).increment(1);
+}
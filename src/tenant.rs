@@ -1,9 +1,20 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use rusqlite::Connection;
+use rusqlite::{Connection, DatabaseName, OpenFlags};
 
 use crate::error::SQLResult;
+use crate::memory::open_memory_database;
+
+/// A tenant as it is registered in the master database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TenantRecord
+{
+    pub tenant_id: String,
+    /// The path to the tenant db file, `None` for in-memory tenants.
+    pub path: Option<PathBuf>,
+    pub created_at: String,
+}
 
 #[derive(Clone)]
 pub struct TenantConnection
@@ -17,6 +28,7 @@ impl TenantConnection
     /// Opens a connection to the sqlite database
     ///
     /// If `None` is provided, then the library defaults to in memory sqlite only.
+    #[allow(clippy::arc_with_non_send_sync)]
     pub fn open<P: AsRef<Path>>(path: Option<P>) -> SQLResult<Self>
     {
         if let Some(p) = path {
@@ -29,4 +41,55 @@ impl TenantConnection
             })
         }
     }
+
+    /// Opens a connection to a tenant database file that must already exist, unlike `open` which creates it.
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn open_existing<P: AsRef<Path>>(path: P) -> SQLResult<Self>
+    {
+        Ok(Self {
+            connection: Arc::new(open_existing_file(path)?),
+        })
+    }
+
+    /// Opens another connection to the shared-cache in-memory database of a tenant.
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub(crate) fn open_shared_memory(uri: &str) -> SQLResult<Self>
+    {
+        Ok(Self {
+            connection: Arc::new(open_memory_database(uri)?),
+        })
+    }
+
+    /// Opens a connection to a tenant database file that fails to run any statement that writes.
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> SQLResult<Self>
+    {
+        Ok(Self {
+            connection: Arc::new(Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?),
+        })
+    }
+
+    /// Returns `true` if the connection was opened read-only.
+    pub fn is_read_only(&self) -> bool
+    {
+        self.connection.is_readonly(DatabaseName::Main).unwrap_or(false)
+    }
+
+    /// Returns `true` if the connection is to an in-memory database.
+    pub fn is_in_memory(&self) -> bool
+    {
+        self.connection.path().is_none_or(str::is_empty)
+    }
+}
+
+/// Opens a registered tenant file read-write, without creating it if it was deleted.
+pub(crate) fn open_existing_file<P: AsRef<Path>>(path: P) -> SQLResult<Connection>
+{
+    Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
 }
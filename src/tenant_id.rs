@@ -0,0 +1,243 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use crate::error::MultiTenantError;
+
+/// The longest tenant id accepted, in bytes.
+pub const MAX_TENANT_ID_LEN: usize = 128;
+
+/// Names Windows reserves for devices, which can not be used as file names whatever their case.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2",
+    "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// A validated tenant id.
+///
+/// Ids are 1 to `MAX_TENANT_ID_LEN` ASCII letters, digits, `-`, `_` or `.`, do not start with a `.`, and are not a
+/// name Windows reserves for devices such as `CON` or `NUL`. So an id is always safe to use as a file name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TenantId(String);
+
+impl TenantId
+{
+    pub fn new<S: Into<String>>(tenant_id: S) -> Result<Self, MultiTenantError>
+    {
+        let tenant_id = tenant_id.into();
+        Self::validate(&tenant_id)?;
+        Ok(Self(tenant_id))
+    }
+
+    /// Checks that `tenant_id` would make a valid `TenantId`.
+    pub fn validate(tenant_id: &str) -> Result<(), MultiTenantError>
+    {
+        let invalid = |reason: &str| Err(MultiTenantError::InvalidTenantId(format!("'{}' {}", tenant_id, reason)));
+
+        if tenant_id.is_empty() {
+            return invalid("is empty");
+        }
+
+        if tenant_id.len() > MAX_TENANT_ID_LEN {
+            return invalid(&format!("is longer than {} bytes", MAX_TENANT_ID_LEN));
+        }
+
+        if let Some(c) = tenant_id
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        {
+            return invalid(&format!(
+                "contains {:?}, only letters, digits, '-', '_' and '.' are allowed",
+                c
+            ));
+        }
+
+        if tenant_id.starts_with('.') {
+            return invalid("starts with '.'");
+        }
+
+        let stem = tenant_id.split('.').next().unwrap_or_default();
+
+        if RESERVED_NAMES.iter().any(|name| name.eq_ignore_ascii_case(stem)) {
+            return invalid("is a reserved name");
+        }
+
+        Ok(())
+    }
+
+    pub fn as_str(&self) -> &str
+    {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String
+    {
+        self.0
+    }
+}
+
+impl fmt::Display for TenantId
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.write_str(&self.0)
+    }
+}
+
+impl Deref for TenantId
+{
+    type Target = str;
+
+    fn deref(&self) -> &str
+    {
+        &self.0
+    }
+}
+
+impl AsRef<str> for TenantId
+{
+    fn as_ref(&self) -> &str
+    {
+        &self.0
+    }
+}
+
+impl Borrow<str> for TenantId
+{
+    fn borrow(&self) -> &str
+    {
+        &self.0
+    }
+}
+
+impl From<TenantId> for String
+{
+    fn from(tenant_id: TenantId) -> Self
+    {
+        tenant_id.0
+    }
+}
+
+impl FromStr for TenantId
+{
+    type Err = MultiTenantError;
+
+    fn from_str(tenant_id: &str) -> Result<Self, Self::Err>
+    {
+        Self::new(tenant_id)
+    }
+}
+
+impl TryFrom<String> for TenantId
+{
+    type Error = MultiTenantError;
+
+    fn try_from(tenant_id: String) -> Result<Self, Self::Error>
+    {
+        Self::new(tenant_id)
+    }
+}
+
+impl TryFrom<&str> for TenantId
+{
+    type Error = MultiTenantError;
+
+    fn try_from(tenant_id: &str) -> Result<Self, Self::Error>
+    {
+        Self::new(tenant_id)
+    }
+}
+
+/// Formats the id as its lowercase hyphenated form, such as `67e55044-10b1-426f-9247-bb680e5fe0c8`.
+#[cfg(feature = "uuid")]
+impl From<uuid::Uuid> for TenantId
+{
+    fn from(uuid: uuid::Uuid) -> Self
+    {
+        Self(uuid.hyphenated().to_string())
+    }
+}
+
+/// A type that identifies tenants, used as the key of a `TypedTenantManager`.
+///
+/// Keys are stored in the master database as their `TenantId`, so `from_tenant_id(key.to_tenant_id()?)` must give
+/// back the same key.
+pub trait TenantKey: Sized
+{
+    fn to_tenant_id(&self) -> Result<TenantId, MultiTenantError>;
+
+    fn from_tenant_id(tenant_id: &TenantId) -> Result<Self, MultiTenantError>;
+}
+
+impl TenantKey for TenantId
+{
+    fn to_tenant_id(&self) -> Result<TenantId, MultiTenantError>
+    {
+        Ok(self.clone())
+    }
+
+    fn from_tenant_id(tenant_id: &TenantId) -> Result<Self, MultiTenantError>
+    {
+        Ok(tenant_id.clone())
+    }
+}
+
+impl TenantKey for String
+{
+    fn to_tenant_id(&self) -> Result<TenantId, MultiTenantError>
+    {
+        TenantId::new(self.as_str())
+    }
+
+    fn from_tenant_id(tenant_id: &TenantId) -> Result<Self, MultiTenantError>
+    {
+        Ok(tenant_id.to_string())
+    }
+}
+
+macro_rules! integer_tenant_id {
+    ($($int:ty),*) => {
+        $(
+            impl From<$int> for TenantId
+            {
+                fn from(tenant_id: $int) -> Self
+                {
+                    Self(tenant_id.to_string())
+                }
+            }
+
+            impl TenantKey for $int
+            {
+                fn to_tenant_id(&self) -> Result<TenantId, MultiTenantError>
+                {
+                    Ok(TenantId::from(*self))
+                }
+
+                fn from_tenant_id(tenant_id: &TenantId) -> Result<Self, MultiTenantError>
+                {
+                    tenant_id.parse().map_err(|_| {
+                        MultiTenantError::InvalidTenantId(format!("'{}' is not a {}", tenant_id, stringify!($int)))
+                    })
+                }
+            }
+        )*
+    };
+}
+
+integer_tenant_id!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+#[cfg(feature = "uuid")]
+impl TenantKey for uuid::Uuid
+{
+    fn to_tenant_id(&self) -> Result<TenantId, MultiTenantError>
+    {
+        Ok(TenantId::from(*self))
+    }
+
+    fn from_tenant_id(tenant_id: &TenantId) -> Result<Self, MultiTenantError>
+    {
+        uuid::Uuid::parse_str(tenant_id)
+            .map_err(|err| MultiTenantError::InvalidTenantId(format!("'{}' is not a uuid: {}", tenant_id, err)))
+    }
+}
@@ -0,0 +1,123 @@
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+
+use crate::access::AccessMode;
+use crate::config::Configuration;
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+use crate::shutdown::ShutdownReport;
+use crate::tenant::{TenantConnection, TenantRecord};
+use crate::tenant_id::{TenantId, TenantKey};
+
+/// A `MultiTenantManager` keyed by your own tenant id type, such as a `Uuid` or an integer.
+///
+/// The core tenant methods take a `&K`, and every other method of the manager is reachable through `Deref` with
+/// the `TenantId` of a key, see `TenantKey::to_tenant_id`.
+pub struct TypedTenantManager<K: TenantKey>
+{
+    manager: MultiTenantManager,
+    key: PhantomData<K>,
+}
+
+impl<K: TenantKey> TypedTenantManager<K>
+{
+    pub fn new(config: Configuration) -> SQLResult<Self>
+    {
+        Ok(Self::from(MultiTenantManager::new(config)?))
+    }
+
+    pub fn into_inner(self) -> MultiTenantManager
+    {
+        self.manager
+    }
+
+    /// See `MultiTenantManager::shutdown`.
+    pub fn shutdown(self) -> ShutdownReport
+    {
+        self.manager.shutdown()
+    }
+
+    /// See `MultiTenantManager::add_tenant`.
+    pub fn add_tenant(&mut self, key: &K, path: Option<PathBuf>) -> SQLResult<(), MultiTenantError>
+    {
+        self.manager.add_tenant(&key.to_tenant_id()?, path)
+    }
+
+    /// See `MultiTenantManager::remove_tenant`.
+    pub fn remove_tenant(&mut self, key: &K) -> SQLResult<(), MultiTenantError>
+    {
+        self.manager.remove_tenant(&key.to_tenant_id()?)
+    }
+
+    /// See `MultiTenantManager::rename_tenant`.
+    pub fn rename_tenant(&mut self, old_key: &K, new_key: &K) -> SQLResult<(), MultiTenantError>
+    {
+        self.manager.rename_tenant(&old_key.to_tenant_id()?, &new_key.to_tenant_id()?)
+    }
+
+    /// See `MultiTenantManager::get_connection`.
+    pub fn get_connection(&mut self, key: &K) -> SQLResult<Option<TenantConnection>, MultiTenantError>
+    {
+        self.manager.get_connection(&key.to_tenant_id()?)
+    }
+
+    /// See `MultiTenantManager::get_connection_readonly`.
+    pub fn get_connection_readonly(&mut self, key: &K) -> SQLResult<Option<TenantConnection>, MultiTenantError>
+    {
+        self.manager.get_connection_readonly(&key.to_tenant_id()?)
+    }
+
+    /// See `MultiTenantManager::get_tenant`.
+    pub fn get_tenant(&self, key: &K) -> SQLResult<Option<TenantRecord>, MultiTenantError>
+    {
+        self.manager.get_tenant(&key.to_tenant_id()?)
+    }
+
+    /// See `MultiTenantManager::set_access_mode`.
+    pub fn set_access_mode(&mut self, key: &K, mode: AccessMode) -> SQLResult<(), MultiTenantError>
+    {
+        self.manager.set_access_mode(&key.to_tenant_id()?, mode)
+    }
+
+    /// Lists the keys of every tenant, in the order they were added.
+    ///
+    /// Fails if a tenant was registered under an id that is not a valid `K`.
+    pub fn tenant_keys(&self) -> SQLResult<Vec<K>, MultiTenantError>
+    {
+        self.manager
+            .list_tenants()?
+            .into_iter()
+            .map(|tenant| K::from_tenant_id(&TenantId::new(tenant.tenant_id)?))
+            .collect()
+    }
+}
+
+impl<K: TenantKey> From<MultiTenantManager> for TypedTenantManager<K>
+{
+    fn from(manager: MultiTenantManager) -> Self
+    {
+        Self {
+            manager,
+            key: PhantomData,
+        }
+    }
+}
+
+impl<K: TenantKey> Deref for TypedTenantManager<K>
+{
+    type Target = MultiTenantManager;
+
+    fn deref(&self) -> &MultiTenantManager
+    {
+        &self.manager
+    }
+}
+
+impl<K: TenantKey> DerefMut for TypedTenantManager<K>
+{
+    fn deref_mut(&mut self) -> &mut MultiTenantManager
+    {
+        &mut self.manager
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use log::{info, warn};
+use rusqlite::{params, Connection, Row};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+use crate::statements::{quote_identifier, SqlStatement};
+use crate::stats::unix_millis;
+use crate::tenant::TenantConnection;
+
+/// The size of a table in a tenant database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableUsage
+{
+    pub name: String,
+    pub row_count: u64,
+    /// Bytes used by the table, `None` if SQLite was built without the `dbstat` table.
+    pub size: Option<u64>,
+    /// Bytes used by the indexes of the table, `None` if SQLite was built without the `dbstat` table.
+    pub index_size: Option<u64>,
+}
+
+/// How much storage a tenant uses, see `MultiTenantManager::tenant_usage`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TenantUsage
+{
+    pub tenant_id: String,
+    pub page_count: u64,
+    pub page_size: u64,
+    pub freelist_count: u64,
+    /// Bytes on disk, the database file and its WAL. `0` for in-memory tenants.
+    pub file_size: u64,
+    /// Bytes in the WAL file, which are included in `file_size`.
+    pub wal_size: u64,
+    pub tables: Vec<TableUsage>,
+}
+
+/// The storage a tenant used when it was last measured, as cached in the master database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageSummary
+{
+    pub tenant_id: String,
+    pub page_count: u64,
+    pub page_size: u64,
+    pub freelist_count: u64,
+    pub file_size: u64,
+    /// UTC timestamp of the measurement.
+    pub measured_at: String,
+}
+
+/// The storage used by every tenant, see `MultiTenantManager::fleet_usage`.
+#[derive(Debug, Default)]
+pub struct FleetUsage
+{
+    pub tenants: Vec<UsageSummary>,
+    /// Tenants that could not be measured, with the error.
+    pub failed: Vec<(String, MultiTenantError)>,
+    /// Bytes on disk across every tenant, WAL files included.
+    pub total_file_size: u64,
+    /// Bytes in the pages of every tenant, in-memory ones included.
+    pub total_database_size: u64,
+    /// Bytes in the free pages of every tenant, which `VACUUM` could give back.
+    pub total_free_size: u64,
+}
+
+impl MultiTenantManager
+{
+    /// Measures how much storage a tenant uses, down to the rows and bytes of each table.
+    ///
+    /// Counting rows reads every table, so this is as slow as a full scan of the tenant. File backed tenants are
+    /// measured on their own read-only connection, so the cache is left alone. In-memory tenants fail unless this
+    /// manager has their database open. The totals are cached in the master database for `fleet_usage`.
+    pub fn tenant_usage(&mut self, tenant_id: &str) -> SQLResult<TenantUsage, MultiTenantError>
+    {
+        let usage = self.measure_usage(tenant_id, true)?;
+        self.cache_usage(&usage)?;
+
+        Ok(usage)
+    }
+
+    /// Gets the storage used by every tenant, measuring those without a cached measurement newer than `max_age`.
+    ///
+    /// Fresh measurements are cached in the master database with their timestamp, so a `max_age` of zero measures
+    /// every tenant again. Table level usage is not measured, see `tenant_usage` for it.
+    pub fn fleet_usage(&mut self, max_age: Duration) -> SQLResult<FleetUsage, MultiTenantError>
+    {
+        let measured_since = unix_millis(SystemTime::now().checked_sub(max_age).unwrap_or(SystemTime::UNIX_EPOCH));
+
+        let mut cached: HashMap<String, UsageSummary> = {
+            let mut statement = self.master_db.prepare(SqlStatement::SelectUsageSince.as_str())?;
+
+            let summaries = statement
+                .query_map(params![measured_since], Self::usage_summary_from_row)?
+                .collect::<SQLResult<Vec<_>>>()?;
+
+            summaries
+                .into_iter()
+                .map(|summary| (summary.tenant_id.clone(), summary))
+                .collect()
+        };
+
+        let mut fleet = FleetUsage::default();
+
+        for tenant in self.list_tenants()? {
+            let summary = match cached.remove(&tenant.tenant_id) {
+                Some(summary) => summary,
+                None => match self
+                    .measure_usage(&tenant.tenant_id, false)
+                    .and_then(|usage| self.cache_usage(&usage))
+                {
+                    Ok(summary) => summary,
+                    Err(err) => {
+                        warn!("Failed to measure the usage of ({}) tenant: {}", tenant.tenant_id, err);
+                        fleet.failed.push((tenant.tenant_id, err));
+                        continue;
+                    }
+                },
+            };
+
+            fleet.total_file_size += summary.file_size;
+            fleet.total_database_size += summary.page_count * summary.page_size;
+            fleet.total_free_size += summary.freelist_count * summary.page_size;
+            fleet.tenants.push(summary);
+        }
+
+        info!(
+            "Measured fleet usage: {} tenants using {} bytes on disk.",
+            fleet.tenants.len(),
+            fleet.total_file_size
+        );
+
+        Ok(fleet)
+    }
+
+    fn measure_usage(&mut self, tenant_id: &str, with_tables: bool) -> SQLResult<TenantUsage, MultiTenantError>
+    {
+        let tenant = self
+            .get_tenant(tenant_id)?
+            .ok_or_else(|| MultiTenantError::TenantNotFound(tenant_id.to_string()))?;
+
+        let (connection, file_size, wal_size) = match &tenant.path {
+            Some(path) => {
+                let metadata = fs::metadata(path).map_err(|err| match err.kind() {
+                    ErrorKind::NotFound => {
+                        MultiTenantError::TenantFileMissing(format!("'{}' at {}", tenant_id, path.display()))
+                    }
+                    _ => MultiTenantError::DatabaseError(format!("Failed to read {}: {}", path.display(), err)),
+                })?;
+
+                let mut wal = path.as_os_str().to_owned();
+                wal.push("-wal");
+                let wal_size = fs::metadata(Path::new(&wal)).map(|wal| wal.len()).unwrap_or_default();
+
+                (TenantConnection::open_read_only(path)?, metadata.len() + wal_size, wal_size)
+            }
+            // Only measured while this manager has the database open, a new one would always be empty.
+            None => (self.open_anchored_memory_tenant(tenant_id)?, 0, 0),
+        };
+
+        let connection = &connection.connection;
+        let pragma = |name: &str| connection.query_row(&format!("PRAGMA {};", name), [], |row| row.get::<_, u64>(0));
+
+        Ok(TenantUsage {
+            tenant_id: tenant_id.to_string(),
+            page_count: pragma("page_count")?,
+            page_size: pragma("page_size")?,
+            freelist_count: pragma("freelist_count")?,
+            file_size,
+            wal_size,
+            tables: if with_tables {
+                Self::table_usage(connection)?
+            } else {
+                Vec::new()
+            },
+        })
+    }
+
+    /// Counts the rows of every table, and sizes them with `dbstat` if SQLite has it.
+    fn table_usage(connection: &Connection) -> SQLResult<Vec<TableUsage>>
+    {
+        let names: Vec<String> = connection
+            .prepare("SELECT name FROM sqlite_schema WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name;")?
+            .query_map([], |row| row.get(0))?
+            .collect::<SQLResult<_>>()?;
+
+        // (table size, index size) keyed by table name.
+        let sizes: Option<HashMap<String, (u64, u64)>> = connection
+            .prepare(
+                "SELECT s.tbl_name, SUM(CASE WHEN s.type = 'table' THEN d.pgsize ELSE 0 END), SUM(CASE WHEN s.type = \
+                 'index' THEN d.pgsize ELSE 0 END) FROM dbstat AS d JOIN sqlite_schema AS s ON s.name = d.name WHERE \
+                 d.aggregate = TRUE GROUP BY s.tbl_name;",
+            )
+            .and_then(|mut statement| {
+                statement
+                    .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+                    .collect()
+            })
+            .ok();
+
+        names
+            .into_iter()
+            .map(|name| {
+                let row_count =
+                    connection.query_row(&format!("SELECT COUNT(*) FROM {};", quote_identifier(&name)), [], |row| {
+                        row.get(0)
+                    })?;
+                let size = sizes.as_ref().map(|sizes| sizes.get(&name).copied().unwrap_or_default());
+
+                Ok(TableUsage {
+                    row_count,
+                    size: size.map(|(size, _)| size),
+                    index_size: size.map(|(_, index_size)| index_size),
+                    name,
+                })
+            })
+            .collect()
+    }
+
+    /// Writes the totals of a measurement to the master database.
+    fn cache_usage(&mut self, usage: &TenantUsage) -> SQLResult<UsageSummary, MultiTenantError>
+    {
+        Ok(self.master_db.query_row(
+            SqlStatement::UpsertUsage.as_str(),
+            params![
+                usage.tenant_id,
+                usage.page_count,
+                usage.page_size,
+                usage.freelist_count,
+                usage.file_size
+            ],
+            Self::usage_summary_from_row,
+        )?)
+    }
+
+    fn usage_summary_from_row(row: &Row) -> SQLResult<UsageSummary>
+    {
+        Ok(UsageSummary {
+            tenant_id: row.get(0)?,
+            page_count: row.get(1)?,
+            page_size: row.get(2)?,
+            freelist_count: row.get(3)?,
+            file_size: row.get(4)?,
+            measured_at: row.get(5)?,
+        })
+    }
+}
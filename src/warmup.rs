@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+use log::{debug, info, warn};
+use rusqlite::{params, Connection};
+
+use crate::error::{MultiTenantError, SQLResult};
+use crate::manager::MultiTenantManager;
+use crate::statements::SqlStatement;
+use crate::tenant::{open_existing_file, TenantConnection, TenantRecord};
+
+/// Which tenants `warm_cache` preloads.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WarmupStrategy
+{
+    /// The listed tenants, in order.
+    Ids(Vec<String>),
+    /// The tenants with the most recent `last_accessed_at` in the master database.
+    MostRecentlyUsed,
+    /// The tenants flagged as pinned in the master database.
+    Pinned,
+}
+
+/// A connection opened by a warmup thread along with the path it was opened from, waiting to be added to the cache.
+pub(crate) type WarmedConnection = (String, PathBuf, SQLResult<Connection>);
+
+impl MultiTenantManager
+{
+    /// Preloads tenants into the cache so their first request does not pay for opening the file.
+    ///
+    /// Tenant files are opened on a background thread, and the connections are added to the cache by the next
+    /// `get_connection` or `tick` (or `finish_warmup` to wait for them). Only as many tenants as there is free room in
+    /// the cache are loaded, and tenants that are in-memory or already cached are skipped.
+    ///
+    /// Returns the amount of tenants being loaded.
+    pub fn warm_cache(&mut self, strategy: WarmupStrategy) -> SQLResult<usize, MultiTenantError>
+    {
+        let candidates = match strategy {
+            WarmupStrategy::Ids(ids) => {
+                let mut tenants = Vec::with_capacity(ids.len());
+
+                for tenant_id in ids {
+                    match self.get_tenant(&tenant_id)? {
+                        Some(tenant) => tenants.push(tenant),
+                        None => warn!("Skipping warmup of ({}) tenant, it is not registered.", tenant_id),
+                    }
+                }
+
+                tenants
+            }
+            WarmupStrategy::MostRecentlyUsed => self.recently_used_tenants(self.cache.capacity())?,
+            WarmupStrategy::Pinned => self.pinned_tenants()?,
+        };
+
+        let room = self.cache.capacity().saturating_sub(self.cache.evictable_len());
+
+        let tenants: Vec<(String, PathBuf)> = candidates
+            .into_iter()
+            .filter(|tenant| !self.cache.contains(&tenant.tenant_id) && !self.read_only_tenants.contains(&tenant.tenant_id))
+            .filter_map(|tenant| tenant.path.map(|path| (tenant.tenant_id, path)))
+            .take(room)
+            .collect();
+
+        let scheduled = tenants.len();
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for (tenant_id, path) in tenants {
+                let connection = open_existing_file(&path);
+
+                if sender.send((tenant_id, path, connection)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.warmups.push(receiver);
+
+        info!("Warming cache with {} tenants.", scheduled);
+
+        Ok(scheduled)
+    }
+
+    /// Blocks until every running warmup is done, and adds the connections to the cache.
+    ///
+    /// Returns the tenants that were added.
+    pub fn finish_warmup(&mut self) -> Vec<String>
+    {
+        let mut warmed = Vec::new();
+
+        for receiver in std::mem::take(&mut self.warmups) {
+            for (tenant_id, path, connection) in receiver {
+                if self.cache_warmed(&tenant_id, &path, connection) {
+                    warmed.push(tenant_id);
+                }
+            }
+        }
+
+        warmed
+    }
+
+    /// Adds the connections warmup threads have opened so far to the cache, without blocking.
+    pub(crate) fn drain_warmup(&mut self) -> Vec<String>
+    {
+        let mut warmed = Vec::new();
+        let mut running = Vec::with_capacity(self.warmups.len());
+
+        for receiver in std::mem::take(&mut self.warmups) {
+            let finished = Self::try_drain(&receiver, |(tenant_id, path, connection)| {
+                if self.cache_warmed(&tenant_id, &path, connection) {
+                    warmed.push(tenant_id);
+                }
+            });
+
+            if !finished {
+                running.push(receiver);
+            }
+        }
+
+        self.warmups = running;
+        warmed
+    }
+
+    /// Calls `f` for every connection already received. Returns `true` once the warmup thread is done.
+    fn try_drain<F>(receiver: &Receiver<WarmedConnection>, mut f: F) -> bool
+    where
+        F: FnMut(WarmedConnection),
+    {
+        loop {
+            match receiver.try_recv() {
+                Ok(warmed) => f(warmed),
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Disconnected) => return true,
+            }
+        }
+    }
+
+    /// Adds a warmed connection to the cache unless it got there first, or the cache filled up in the meantime.
+    ///
+    /// The tenant is looked up again first, so a warmed connection is dropped if the tenant was removed, renamed, moved
+    /// to another file or made read-only while its file was being opened.
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn cache_warmed(&mut self, tenant_id: &str, path: &Path, connection: SQLResult<Connection>) -> bool
+    {
+        let connection = match connection {
+            Ok(connection) => TenantConnection {
+                connection: Arc::new(connection),
+            },
+            Err(err) => {
+                warn!("Failed to warm ({}) tenant: {}", tenant_id, err);
+                return false;
+            }
+        };
+
+        if !self.is_warmable(tenant_id, path) {
+            debug!("Dropping warmed ({}) tenant, it changed while being opened.", tenant_id);
+            return false;
+        }
+
+        if self.cache.contains(tenant_id) || self.cache.is_full() || self.reserve_connection().is_err() {
+            debug!("Dropping warmed ({}) tenant, there is no room for it.", tenant_id);
+            return false;
+        }
+
+        if let Err(err) = self.apply_quotas(tenant_id, &connection) {
+            warn!("Failed to apply quotas to warmed ({}) tenant: {}", tenant_id, err);
+            return false;
+        }
+
+        self.cache_connection(tenant_id, connection);
+        debug!("Warmed ({}) tenant.", tenant_id);
+
+        true
+    }
+
+    /// Returns `true` if the tenant is still registered at `path` and is not read-only.
+    fn is_warmable(&self, tenant_id: &str, path: &Path) -> bool
+    {
+        if self.read_only_tenants.contains(tenant_id) {
+            return false;
+        }
+
+        match self.get_tenant(tenant_id) {
+            Ok(Some(tenant)) => tenant.path.as_deref() == Some(path),
+            Ok(None) => false,
+            Err(err) => {
+                warn!("Failed to look up warmed ({}) tenant: {}", tenant_id, err);
+                false
+            }
+        }
+    }
+
+    /// Lists the tenants flagged as pinned in the master database.
+    pub(crate) fn pinned_tenants(&self) -> SQLResult<Vec<TenantRecord>, MultiTenantError>
+    {
+        let mut statement = self.master_db.prepare(SqlStatement::SelectPinnedTenants.as_str())?;
+
+        let tenants = statement
+            .query_map([], Self::tenant_record_from_row)?
+            .collect::<SQLResult<Vec<_>>>()?;
+
+        Ok(tenants)
+    }
+
+    fn recently_used_tenants(&self, limit: usize) -> SQLResult<Vec<TenantRecord>, MultiTenantError>
+    {
+        let mut statement = self.master_db.prepare(SqlStatement::SelectRecentlyUsedTenants.as_str())?;
+
+        let tenants = statement
+            .query_map(params![limit], Self::tenant_record_from_row)?
+            .collect::<SQLResult<Vec<_>>>()?;
+
+        Ok(tenants)
+    }
+}